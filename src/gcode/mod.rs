@@ -1,13 +1,108 @@
-use crate::settings::Settings;
+pub mod template;
+
+use crate::settings::{ExtrusionSettings, MaterialSettings, Settings, StringOrVecOfStrings, TravelSettings};
+use std::collections::HashMap;
+use std::f64::consts::PI;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
 
+/// heat up and home, used without a start template
+const DEFAULT_START_GCODE: &str = "\
+M104 S{first_layer_temperature}; heating nozzle to {first_layer_temperature}C without waiting
+M140 S{first_layer_bed_temperature}; heating bed to {first_layer_bed_temperature}C without waiting
+;
+G28 ; home all axes
+M109 S{first_layer_temperature} ; wait for nozzle to reach {first_layer_temperature}C
+M190 S{first_layer_bed_temperature} ; wait for bed to reach {first_layer_bed_temperature}C";
+
+/// cool down and get the nozzle off the part, used without an end template
+const DEFAULT_END_GCODE: &str = "\
+M104 S0 ; nozzle heater off
+M140 S0 ; bed heater off
+M107 ; fan off
+G91 ; relative positioning
+G1 Z10 F600 ; lift the nozzle off the part
+G90 ; absolute positioning
+M84 ; motors off";
+
 pub struct GcodeWriter {
-    file_buffer: File
+    /// running E total in absolute mode, kept in double precision
+    /// since a long print easily reaches tens of meters of filament
+    e: f64,
+    end_gcode: String,
+    extrusion: ExtrusionSettings,
+    /// last fan speed written as M106 S, None before the first
+    fan: Option<u32>,
+    /// last feed rate written, F is modal
+    feed_rate: Option<f32>,
+    file_buffer: File,
+    layer_change_gcode: String,
+    material: MaterialSettings,
+    /// fastest the printer moves z, caps every z move
+    max_z_feed_rate: Option<f32>,
+    /// last xy position written, None before the first move
+    position: Option<[f32; 2]>,
+    /// whether the filament is currently pulled back
+    retracted: bool,
+    start_gcode: String,
+    travel: Option<TravelSettings>,
+    /// values the templates can use that hold for the whole print
+    variables: HashMap<&'static str, String>,
+    /// plastic extruded so far in mm^3
+    volume: f64,
+    /// height of the current layer, the nozzle returns here after a z hop
+    z: f32
 }
 
 impl GcodeWriter {
-    pub fn new(gcode_file: &str) -> Self {
+    pub fn new(gcode_file: &str, settings: &Settings) -> Self {
+        let mut extrusion = settings.extrusion
+            .clone()
+            .expect("Writing gcode needs extrusion settings");
+        let material = settings.material
+            .material()
+            .cloned()
+            .unwrap_or_else(|| panic!(
+                "Writing gcode needs a material profile, look up {:?} in a material library first",
+                settings.material
+            ));
+        if let Some(filament_diameter) = material.filament_diameter {
+            extrusion.filament_diameter = filament_diameter;
+        }
+        let templates = settings.gcode.as_ref();
+        let template = |x: Option<&StringOrVecOfStrings>, default: &str| {
+            x.map_or(default.to_string(), |x| x.text())
+        };
+
+        let mut variables = HashMap::new();
+        variables.insert("bed_temperature", material.bed_temperature.to_string());
+        variables.insert("filament_diameter", extrusion.filament_diameter.to_string());
+        variables.insert("first_layer_bed_temperature", material.first_layer_bed_temperature.to_string());
+        variables.insert("first_layer_temperature", material.first_layer_temperature.to_string());
+        variables.insert("material", material.name.clone());
+        variables.insert("temperature", material.temperature.to_string());
+        if let Some(printer) = &settings.printer {
+            variables.insert("max_height", printer.max_height.to_string());
+            variables.insert("nozzle_diameter", printer.nozzle_diameter.to_string());
+        }
+
+        // catch template mistakes now rather than part way through
+        // the print, layer changes also get the layer
+        let start_gcode = template(templates.and_then(|x| x.start.as_ref()), DEFAULT_START_GCODE);
+        let end_gcode = template(templates.and_then(|x| x.end.as_ref()), DEFAULT_END_GCODE);
+        let layer_change_gcode = template(templates.and_then(|x| x.layer_change.as_ref()), "");
+        let mut layer_variables = variables.clone();
+        layer_variables.insert("layer_num", "0".to_string());
+        layer_variables.insert("layer_z", "0".to_string());
+        for (name, gcode, variables) in [
+            ("start", &start_gcode, &variables),
+            ("end", &end_gcode, &variables),
+            ("layer change", &layer_change_gcode, &layer_variables)
+        ] {
+            if let Err(error) = template::check(gcode, variables) {
+                panic!("Bad {} g-code template: {}", name, error);
+            }
+        }
         let file_buffer = OpenOptions::new()
             .write(true)
             .create(true)
@@ -15,42 +110,185 @@ impl GcodeWriter {
             .open(gcode_file)
             .unwrap();
         Self {
-            file_buffer: file_buffer
+            e: 0.,
+            end_gcode,
+            extrusion,
+            fan: None,
+            feed_rate: None,
+            file_buffer,
+            layer_change_gcode,
+            material,
+            max_z_feed_rate: settings.printer
+                .as_ref()
+                .and_then(|x| x.max_feed_rates.as_ref())
+                .map(|x| x.z),
+            position: None,
+            retracted: false,
+            start_gcode,
+            travel: settings.travel.clone(),
+            variables,
+            volume: 0.,
+            z: 0.
+        }
+    }
+
+    /// E value for extruding a line of the given length
+    ///
+    /// The cross section of the line is modeled as a rectangle with
+    /// semicircular sides, as wide as the line and as tall as the layer,
+    /// and scaled by the flow of the material. In volumetric mode that
+    /// volume is the E value, otherwise it is converted to a length of
+    /// filament.
+    pub fn extrusion_length(&self, length: f32, line_width: f32, layer_height: f32) -> f64 {
+        let volume = self.extrusion_volume(length, line_width, layer_height);
+        if self.extrusion.volumetric_e {
+            volume
+        } else {
+            let d = self.extrusion.filament_diameter as f64;
+            volume / (PI * (d / 2.).powi(2))
         }
     }
 
-    pub fn write_gcode(&mut self, gcode: &str) -> () {
+    /// plastic in mm^3 for a line of the given length
+    fn extrusion_volume(&self, length: f32, line_width: f32, layer_height: f32) -> f64 {
+        let (w, h) = (line_width as f64, layer_height as f64);
+        let area = (w - h).max(0.) * h + PI * (h / 2.).powi(2);
+        area * length as f64 * self.material.flow as f64
+    }
+
+    /// move the extruder by ``de`` and return the E word for it
+    fn advance_e(&mut self, de: f64) -> f64 {
+        if self.extrusion.relative_e {
+            de
+        } else {
+            self.e += de;
+            self.e
+        }
+    }
+
+    /// " F..." when the feed rate changes, empty otherwise
+    fn feed_rate_word(&mut self, feed_rate: f32) -> String {
+        if self.feed_rate == Some(feed_rate) {
+            String::new()
+        } else {
+            self.feed_rate = Some(feed_rate);
+            format!(" F{}", feed_rate)
+        }
+    }
+
+    /// F word for a z move, no faster than the printer moves z
+    fn z_feed_rate_word(&mut self, feed_rate: f32) -> String {
+        let feed_rate = self.max_z_feed_rate.map_or(feed_rate, |x| feed_rate.min(x));
+        self.feed_rate_word(feed_rate)
+    }
+
+    /// E units for a length of filament, which differ in volumetric mode
+    pub fn filament_to_e(&self, length: f32) -> f64 {
+        let length = length as f64;
+        if self.extrusion.volumetric_e {
+            let d = self.extrusion.filament_diameter as f64;
+            length * PI * (d / 2.).powi(2)
+        } else {
+            length
+        }
+    }
+
+    /// last xy position written, None before the first move
+    pub fn position(&self) -> Option<[f32; 2]> {
+        self.position
+    }
+
+    /// set the part cooling fan to a fraction of full speed unless it
+    /// already runs at it
+    pub fn write_fan(&mut self, speed: f32) {
+        let s = (speed.clamp(0., 1.) * 255.).round() as u32;
+        if self.fan == Some(s) {
+            return;
+        }
+        self.fan = Some(s);
+        if s == 0 {
+            self.write_gcode("M107 ; fan off");
+        } else {
+            self.write_gcode(format!("M106 S{} ; fan at {:.0}%", s, 100. * speed.clamp(0., 1.)).as_str());
+        }
+    }
+
+    pub fn write_gcode(&mut self, gcode: &str) {
         let _ = writeln!(self.file_buffer, "{}", gcode);
-    } 
+    }
 
-    pub fn write_header(&mut self, settings: &Settings) -> () {
+    pub fn write_header(&mut self, settings: &Settings) {
         let _ = writeln!(
-            self.file_buffer, "; Generated with {} {}", 
+            self.file_buffer, "; Generated with {} {}",
             env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")
         );
         self.write_gcode(format!("; {} settings:", env!("CARGO_PKG_NAME")).as_str());
         self.write_gcode(&settings.to_gcode_comment());
         self.write_gcode("G21; units in milimeters"); // TODO
         self.write_gcode("G90; absolute positioning"); // TODO
-        self.write_gcode("M82; extruder set to absolute mode\n;"); // TODO
-        // TODO need to specialize this to type and what not
-        self.write_gcode("M104 S200; heating nozzle to 200C without waiting");
-        self.write_gcode("M140 S60; heating bed to 60C without waiting\n;");
-        self.write_home_all();
-        self.write_gcode("M109 S200 ; wait for nozzle to reach 200C"); // TODO
-        self.write_gcode("M190 S60 ; wait for bed to reach 60C"); // TODO
-        self.write_gcode("G92 E0; zero the extruder");
+        if self.extrusion.relative_e {
+            self.write_gcode("M83; extruder set to relative mode");
+        } else {
+            self.write_gcode("M82; extruder set to absolute mode");
+        }
+        if self.extrusion.volumetric_e {
+            self.write_gcode(
+                format!(
+                    "M200 D{}; volumetric extrusion for {}mm filament",
+                    self.extrusion.filament_diameter, self.extrusion.filament_diameter
+                ).as_str()
+            );
+        }
+        if let Some(printer) = &settings.printer {
+            // firmware takes max feed rates in mm/s
+            if let Some(x) = &printer.max_feed_rates {
+                self.write_gcode(
+                    format!(
+                        "M203 X{} Y{} Z{} E{}; max feed rates",
+                        x.x / 60., x.y / 60., x.z / 60., x.e / 60.
+                    ).as_str()
+                );
+            }
+            if let Some(x) = &printer.max_accelerations {
+                self.write_gcode(
+                    format!("M201 X{} Y{} Z{} E{}; max accelerations", x.x, x.y, x.z, x.e).as_str()
+                );
+            }
+        }
+        self.write_gcode(";");
+        let start = template::render(&self.start_gcode, &self.variables);
+        self.write_gcode(&start);
+        // the start g-code may move anywhere
+        self.position = None;
+        self.write_reset_extruder();
+    }
+
+    /// retract and write the end g-code after the last layer
+    pub fn write_end(&mut self) {
+        self.write_retract();
+        self.write_gcode(";\n; End\n;");
+        let end = template::render(&self.end_gcode, &self.variables);
+        self.write_gcode(&end);
+
+        let d = self.extrusion.filament_diameter as f64;
+        let length = self.volume / (PI * (d / 2.).powi(2));
+        // mm^3 to cm^3
+        let weight = self.volume / 1000. * self.material.density as f64;
+        println!("Filament used: {:.1} mm, {:.1} g", length, weight);
+        self.write_gcode(format!("; filament used = {:.1} mm", length).as_str());
+        self.write_gcode(format!("; filament used = {:.2} g", weight).as_str());
     }
 
-    pub fn write_home_all(&mut self) -> () {
+    pub fn write_home_all(&mut self) {
         self.write_gcode("G28 ; home all axes");
+        self.position = None;
     }
 
     pub fn write_layer_change(
-        &mut self, 
-        n: u32, z: f32, 
+        &mut self,
+        n: u32, z: f32,
         feed_axis: char, feed_rate: f32
-    ) -> () {
+    ) {
         self.write_gcode(format!(";\n; Layer {}\n;", n).as_str());
         self.write_gcode(
             format!(
@@ -58,17 +296,237 @@ impl GcodeWriter {
                 z, feed_axis, feed_rate
             ).as_str()
         );
+        if feed_axis == 'F' {
+            self.feed_rate = Some(feed_rate);
+        }
+        self.z = z;
+        if n == 1 {
+            self.write_layer_temperatures();
+        }
+        if !self.layer_change_gcode.is_empty() {
+            let mut variables = self.variables.clone();
+            variables.insert("layer_num", n.to_string());
+            variables.insert("layer_z", z.to_string());
+            let gcode = template::render(&self.layer_change_gcode, &variables);
+            if !gcode.is_empty() {
+                self.write_gcode(&gcode);
+            }
+        }
+    }
+
+    /// switch from the first layer temperatures to the later ones
+    fn write_layer_temperatures(&mut self) {
+        let material = self.material.clone();
+        if material.temperature != material.first_layer_temperature {
+            self.write_gcode(
+                format!("M104 S{}; nozzle to {}C for later layers", material.temperature, material.temperature).as_str()
+            );
+        }
+        if material.bed_temperature != material.first_layer_bed_temperature {
+            self.write_gcode(
+                format!("M140 S{}; bed to {}C for later layers", material.bed_temperature, material.bed_temperature).as_str()
+            );
+        }
     }
-    pub fn write_perimeter(
+
+    pub fn write_raft_layer_change(&mut self, n: u32, z: f32, feed_rate: f32) {
+        self.write_gcode(format!(";\n; Raft layer {}\n;", n).as_str());
+        let f = self.z_feed_rate_word(feed_rate);
+        self.write_gcode(format!("G1 Z{}{} ; raft layer change", z, f).as_str());
+        self.z = z;
+    }
+
+    /// extruding move from the current position to x, y
+    ///
+    /// Before the first move the position is unknown, so nothing is
+    /// extruded and the nozzle only travels there.
+    pub fn write_extrusion(
         &mut self,
-        x: f32, y: f32, z: f32, s:f32,
-        extrusion: f32
-    ) -> () {
+        x: f32, y: f32,
+        layer_height: f32, line_width: f32,
+        feed_rate: f32
+    ) {
+        let Some(from) = self.position else {
+            self.write_travel(x, y);
+            return;
+        };
+        self.write_unretract();
+        let length = ((x - from[0]).powi(2) + (y - from[1]).powi(2)).sqrt();
+        let de = self.extrusion_length(length, line_width, layer_height);
+        self.volume += self.extrusion_volume(length, line_width, layer_height);
+        self.position = Some([x, y]);
+
+        let e = self.advance_e(de);
+        let f = self.feed_rate_word(feed_rate);
         self.write_gcode(
             format!(
-                "G1 X{} Y{} E{} ; Move while extruding",
-                x, y, s
+                "G1 X{:.3} Y{:.3} E{:.5}{} ; Move while extruding",
+                x, y, e, f
             ).as_str()
         );
     }
-}
\ No newline at end of file
+
+    /// pull the filament back unless it already is
+    pub fn write_retract(&mut self) {
+        let Some(travel) = self.travel.clone() else {
+            return;
+        };
+        if self.retracted || (travel.retraction_distance <= 0. && !travel.firmware_retraction) {
+            return;
+        }
+        self.retracted = true;
+        if travel.firmware_retraction {
+            self.write_gcode("G10 ; retract");
+            return;
+        }
+        let f = self.feed_rate_word(travel.retraction_feed_rate);
+        let de = self.filament_to_e(-travel.retraction_distance);
+        let e = self.advance_e(de);
+        self.write_gcode(format!("G1 E{:.5}{} ; retract", e, f).as_str());
+    }
+
+    /// move to x, y without extruding
+    ///
+    /// Travels longer than the minimum retract first and, with a z hop
+    /// configured, lift the nozzle while moving. The filament is primed
+    /// again right before the next extruding move.
+    pub fn write_travel(&mut self, x: f32, y: f32) {
+        let length = self.position
+            .map(|from| ((x - from[0]).powi(2) + (y - from[1]).powi(2)).sqrt());
+        if length == Some(0.) {
+            return;
+        }
+        let travel = self.travel.clone();
+        let retract = match (&travel, length) {
+            (Some(travel), Some(length)) => length >= travel.retraction_min_travel,
+            _ => false
+        };
+        if retract {
+            self.write_retract();
+        }
+
+        let z_hop = travel.as_ref().map_or(0., |x| x.z_hop);
+        let hop = retract && self.retracted && z_hop > 0.;
+        let hop_feed_rate = travel.as_ref().map_or(0., |x| x.feed_rate);
+        if hop {
+            let z = self.z + z_hop;
+            let f = self.z_feed_rate_word(hop_feed_rate);
+            self.write_gcode(format!("G0 Z{:.3}{} ; z hop", z, f).as_str());
+        }
+        let f = match &travel {
+            Some(travel) => self.feed_rate_word(travel.feed_rate),
+            None => String::new()
+        };
+        self.write_gcode(format!("G0 X{:.3} Y{:.3}{} ; Travel", x, y, f).as_str());
+        if hop {
+            let z = self.z;
+            let f = self.z_feed_rate_word(hop_feed_rate);
+            self.write_gcode(format!("G0 Z{:.3}{} ; drop back from z hop", z, f).as_str());
+        }
+        self.position = Some([x, y]);
+    }
+
+    /// push the filament back after a retraction
+    pub fn write_unretract(&mut self) {
+        if !self.retracted {
+            return;
+        }
+        self.retracted = false;
+        let travel = self.travel.clone().unwrap();
+        if travel.firmware_retraction {
+            self.write_gcode("G11 ; unretract");
+            if travel.retraction_extra_prime <= 0. {
+                return;
+            }
+        }
+        let prime = if travel.firmware_retraction {
+            travel.retraction_extra_prime
+        } else {
+            travel.retraction_distance + travel.retraction_extra_prime
+        };
+        let f = self.feed_rate_word(travel.retraction_feed_rate);
+        let de = self.filament_to_e(prime);
+        let e = self.advance_e(de);
+        self.write_gcode(format!("G1 E{:.5}{} ; unretract", e, f).as_str());
+    }
+
+    pub fn write_reset_extruder(&mut self) {
+        self.write_gcode("G92 E0; zero the extruder");
+        self.e = 0.;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// writer for a throwaway file with the given extrusion settings
+    fn new_writer(name: &str, extrusion: &str) -> GcodeWriter {
+        let settings: Settings = serde_json::from_str(&format!(
+            r#"{{
+                "name": "test",
+                "extrusion": {},
+                "layer_height": {{"layer_0_height": 0.2, "layer_n_height": 0.2}},
+                "material": {{
+                    "bed_temperature": 60.0, "density": 1.24, "flow": 0.9,
+                    "first_layer_bed_temperature": 60.0, "first_layer_temperature": 210.0,
+                    "temperature": 205.0
+                }}
+            }}"#,
+            extrusion
+        )).unwrap();
+        let file = std::env::temp_dir().join(format!("slicey_{}.gcode", name));
+        GcodeWriter::new(file.to_str().unwrap(), &settings)
+    }
+
+    /// a 10 mm line, 0.4 wide and 0.2 tall: a 0.2 x 0.2 rectangle with
+    /// a half circle of diameter 0.2 on either side, at 90% flow
+    fn line_volume() -> f64 {
+        (0.2 * 0.2 + PI * 0.1f64.powi(2)) * 10. * 0.9
+    }
+
+    fn filament_area() -> f64 {
+        PI * (1.75f64 / 2.).powi(2)
+    }
+
+    #[test]
+    fn filament_e() {
+        let writer = new_writer("filament_e", r#"{"filament_diameter": 1.75, "line_width": 0.4}"#);
+        let e = writer.extrusion_length(10., 0.4, 0.2);
+        assert!((e - line_volume() / filament_area()).abs() < 1e-6, "got {}", e);
+        assert!((writer.filament_to_e(0.8) - 0.8).abs() < 1e-6);
+        // a line no wider than it is tall is just the circle
+        let e = writer.extrusion_length(10., 0.1, 0.2);
+        assert!((e - PI * 0.01 * 10. * 0.9 / filament_area()).abs() < 1e-6, "got {}", e);
+    }
+
+    #[test]
+    fn volumetric_e() {
+        let writer = new_writer(
+            "volumetric_e",
+            r#"{"filament_diameter": 1.75, "line_width": 0.4, "volumetric_e": true}"#
+        );
+        let e = writer.extrusion_length(10., 0.4, 0.2);
+        assert!((e - line_volume()).abs() < 1e-6, "got {}", e);
+        // retractions are a length of filament, so become a volume
+        assert!((writer.filament_to_e(0.8) - 0.8 * filament_area()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn relative_e_does_not_accumulate() {
+        let mut writer = new_writer(
+            "relative_e",
+            r#"{"filament_diameter": 1.75, "line_width": 0.4, "relative_e": true}"#
+        );
+        assert_eq!(writer.advance_e(0.5), 0.5);
+        assert_eq!(writer.advance_e(0.25), 0.25);
+        assert_eq!(writer.advance_e(-0.8), -0.8);
+
+        let mut writer = new_writer("absolute_e", r#"{"filament_diameter": 1.75, "line_width": 0.4}"#);
+        assert_eq!(writer.advance_e(0.5), 0.5);
+        assert_eq!(writer.advance_e(0.25), 0.75);
+        assert!((writer.advance_e(-0.8) + 0.05).abs() < 1e-9);
+        writer.write_reset_extruder();
+        assert_eq!(writer.advance_e(0.25), 0.25);
+    }
+}
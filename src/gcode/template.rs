@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+/// comparisons a condition can use, two character ones first so
+/// ``<=`` is not read as ``<``
+const OPERATORS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+/// fill in a g-code template
+///
+/// ``{name}`` is replaced by the value of the variable. Text between
+/// ``{if condition}`` and ``{endif}`` is only kept when the condition
+/// holds, with an optional ``{else}`` in between, and ifs may nest. A
+/// condition is either a single value, which holds unless it is empty,
+/// zero or false, or two values compared with one of ``==``, ``!=``,
+/// ``<``, ``<=``, ``>`` or ``>=``. Values are variable names, numbers,
+/// true, false or text in double quotes, and compare as numbers when
+/// both sides are.
+///
+/// Conditions of branches that are not taken are not looked at.
+/// Rendering never fails, mistakes are for ``check`` to catch up
+/// front: unknown placeholders and unclosed braces are written as
+/// they are and conditions that cannot be decided do not hold.
+pub fn render(template: &str, variables: &HashMap<&str, String>) -> String {
+    let mut gcode = String::new();
+    // condition of every open if, text is kept while they all hold
+    let mut conditions: Vec<bool> = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let active = conditions.iter().all(|x| *x);
+        let Some(end) = rest[start..].find('}').map(|x| start + x) else {
+            break;
+        };
+        if active {
+            gcode.push_str(&rest[..start]);
+        }
+        let tag = rest[start + 1..end].trim();
+        if let Some(condition) = tag.strip_prefix("if ") {
+            conditions.push(active && holds(condition, variables).unwrap_or(false));
+        } else if tag == "else" {
+            if let Some(condition) = conditions.last_mut() {
+                *condition = !*condition;
+            }
+        } else if tag == "endif" {
+            conditions.pop();
+        } else if active {
+            match variables.get(tag) {
+                Some(value) => gcode.push_str(value),
+                None => gcode.push_str(&rest[start..=end])
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    if conditions.iter().all(|x| *x) {
+        gcode.push_str(rest);
+    }
+    gcode
+}
+
+/// the first mistake in a template, if any
+///
+/// Every branch is checked whether it is taken or not, with the
+/// values in ``variables``, which should hold every variable the
+/// template is later rendered with.
+pub fn check(template: &str, variables: &HashMap<&str, String>) -> Result<(), String> {
+    let mut depth = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed {{ at {:?}", &rest[start..]))?;
+        let tag = rest[start + 1..end].trim();
+        if let Some(condition) = tag.strip_prefix("if ") {
+            check_values(condition, variables)?;
+            holds(condition, variables)?;
+            depth += 1;
+        } else if tag == "else" {
+            if depth == 0 {
+                return Err("Got {else} without {if}".to_string());
+            }
+        } else if tag == "endif" {
+            if depth == 0 {
+                return Err("Got {endif} without {if}".to_string());
+            }
+            depth -= 1;
+        } else if !variables.contains_key(tag) {
+            let mut names: Vec<&str> = variables.keys().copied().collect();
+            names.sort();
+            return Err(format!("Unknown variable {{{}}}, the variables are {:?}", tag, names));
+        }
+        rest = &rest[end + 1..];
+    }
+    if depth > 0 {
+        return Err("Got {if} without {endif}".to_string());
+    }
+    Ok(())
+}
+
+/// every value in a condition is a variable, a number, true, false or
+/// quoted text, so a misspelled variable is not quietly taken as text
+fn check_values(condition: &str, variables: &HashMap<&str, String>) -> Result<(), String> {
+    let values = match OPERATORS.iter().find(|x| condition.contains(**x)) {
+        Some(operator) => {
+            let (a, b) = condition.split_once(operator).unwrap();
+            vec![a, b]
+        },
+        None => vec![condition]
+    };
+    for value in values {
+        let value = value.trim();
+        let known = variables.contains_key(value) ||
+            value.parse::<f64>().is_ok() ||
+            value == "true" || value == "false" ||
+            (value.len() >= 2 && value.starts_with('"') && value.ends_with('"'));
+        if !known {
+            return Err(format!(
+                "Unknown variable {:?} in {{if {}}}, text needs double quotes", value, condition.trim()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// whether a condition of an ``{if}`` holds
+fn holds(condition: &str, variables: &HashMap<&str, String>) -> Result<bool, String> {
+    let value = |x: &str| {
+        let x = x.trim();
+        variables.get(x).cloned().unwrap_or_else(|| x.trim_matches('"').to_string())
+    };
+    let Some(operator) = OPERATORS.iter().find(|x| condition.contains(**x)) else {
+        let x = value(condition);
+        return Ok(!(x.is_empty() || x == "false" || x.parse::<f64>() == Ok(0.)));
+    };
+    let (a, b) = condition.split_once(operator).unwrap();
+    let (a, b) = (value(a), value(b));
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => Ok(match *operator {
+            "==" => a == b,
+            "!=" => a != b,
+            "<=" => a <= b,
+            ">=" => a >= b,
+            "<" => a < b,
+            _ => a > b
+        }),
+        _ => match *operator {
+            "==" => Ok(a == b),
+            "!=" => Ok(a != b),
+            _ => Err(format!("Can only compare numbers with {}, got {:?}", operator, condition))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> HashMap<&'static str, String> {
+        HashMap::from([
+            ("layer_num", "2".to_string()),
+            ("material", "PLA".to_string()),
+            ("temperature", "210".to_string())
+        ])
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(render("M104 S{temperature} ; {material}", &variables()), "M104 S210 ; PLA");
+        // unknown ones are left as written, check reports them
+        assert_eq!(render("M117 {temprature}", &variables()), "M117 {temprature}");
+        let error = check("M117 {temprature}", &variables()).unwrap_err();
+        assert!(error.contains("temprature"), "got {}", error);
+        assert!(check("M104 S{temperature}", &variables()).is_ok());
+    }
+
+    #[test]
+    fn nested_conditions() {
+        let template = "{if layer_num > 1}a{if material == \"PLA\"}b{else}c{endif}{else}d{endif}e";
+        assert!(check(template, &variables()).is_ok());
+        assert_eq!(render(template, &variables()), "abe");
+        let mut abs = variables();
+        abs.insert("material", "ABS".to_string());
+        assert_eq!(render(template, &abs), "ace");
+        abs.insert("layer_num", "1".to_string());
+        assert_eq!(render(template, &abs), "de");
+    }
+
+    #[test]
+    fn two_character_operators() {
+        let vars = variables();
+        assert_eq!(render("{if layer_num <= 2}x{endif}", &vars), "x");
+        assert_eq!(render("{if layer_num < 2}x{endif}", &vars), "");
+        assert_eq!(render("{if layer_num >= 2}x{endif}", &vars), "x");
+        assert_eq!(render("{if layer_num > 2}x{endif}", &vars), "");
+        assert_eq!(render("{if layer_num != 2}x{endif}", &vars), "");
+    }
+
+    #[test]
+    fn conditions_are_checked() {
+        // a misspelled variable is not taken as text
+        assert!(check("{if layer_nmu == 2}M106{endif}", &variables()).is_err());
+        assert!(check("{if material == PLA}M106{endif}", &variables()).is_err());
+        assert!(check("{if material < 3}M106{endif}", &variables()).is_err());
+        // checked even in branches that never run, skipped when rendering
+        let template = "{if 0}{if material < 3}x{endif}{endif}y";
+        assert!(check(template, &variables()).is_err());
+        assert_eq!(render(template, &variables()), "y");
+    }
+
+    #[test]
+    fn unbalanced_templates() {
+        assert!(check("M104 {temperature", &variables()).unwrap_err().contains("Unclosed"));
+        assert_eq!(render("M104 {temperature", &variables()), "M104 {temperature");
+        assert!(check("{if 1}x", &variables()).is_err());
+        assert!(check("x{endif}", &variables()).is_err());
+        assert!(check("x{else}", &variables()).is_err());
+    }
+}
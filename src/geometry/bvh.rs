@@ -0,0 +1,360 @@
+use super::{BoundingBox, RayHit, RayIntersection, Triangle};
+use nalgebra::{Point3, Vector3};
+
+/// maximum number of triangles stored in a leaf
+const LEAF_SIZE: usize = 4;
+
+/// padding applied to node boxes so rays grazing
+/// axis aligned faces are not culled by round off
+const BOX_PAD: f32 = 1e-5;
+
+#[derive(Clone, Debug)]
+enum BVHNodeKind {
+    /// indices of the two children in ``BVH::nodes``
+    Branch(usize, usize),
+    /// range into ``BVH::indices``
+    Leaf(usize, usize)
+}
+
+#[derive(Clone, Debug)]
+struct BVHNode {
+    bbox: BoundingBox,
+    kind: BVHNodeKind
+}
+
+/// result of a closest point query
+#[derive(Clone, Copy, Debug)]
+pub struct ClosestPoint {
+    pub point: Point3<f32>,
+    pub distance: f32,
+    pub face: usize
+}
+
+/// bounding volume hierarchy over the triangles of a mesh
+///
+/// The tree only stores triangle indices, so every query takes
+/// the triangle list it was built from. After a transform that
+/// keeps the topology (translate, scale) call ``refit`` rather
+/// than rebuilding.
+#[derive(Clone, Debug)]
+pub struct BVH {
+    indices: Vec<usize>,
+    nodes: Vec<BVHNode>
+}
+
+impl BVH {
+    pub fn new(triangles: &[Triangle]) -> Self {
+        let mut bvh = Self {
+            indices: (0..triangles.len()).collect(),
+            nodes: vec![]
+        };
+        if !triangles.is_empty() {
+            let centroids: Vec<[f32; 3]> = triangles
+                .iter()
+                .map(triangle_centroid)
+                .collect();
+            bvh.build(triangles, &centroids, 0, triangles.len());
+        }
+        bvh
+    }
+
+    /// recursively build the subtree for ``indices[start..end]``
+    /// and return the index of its root node
+    fn build(
+        &mut self,
+        triangles: &[Triangle],
+        centroids: &[[f32; 3]],
+        start: usize,
+        end: usize
+    ) -> usize {
+        let bbox = leaf_box(triangles, &self.indices[start..end]);
+        let node = self.nodes.len();
+        self.nodes.push(BVHNode { bbox, kind: BVHNodeKind::Leaf(start, end) });
+
+        if end - start <= LEAF_SIZE {
+            return node;
+        }
+
+        // split at the median centroid along the longest axis
+        let mut lo = [f32::MAX; 3];
+        let mut hi = [f32::MIN; 3];
+        for &i in &self.indices[start..end] {
+            for d in 0..3 {
+                lo[d] = lo[d].min(centroids[i][d]);
+                hi[d] = hi[d].max(centroids[i][d]);
+            }
+        }
+        let axis = (0..3)
+            .max_by(|&a, &b| (hi[a] - lo[a]).total_cmp(&(hi[b] - lo[b])))
+            .unwrap();
+        if hi[axis] - lo[axis] <= 0. {
+            // every centroid coincides, nothing to split on
+            return node;
+        }
+
+        let mid = start + (end - start) / 2;
+        self.indices[start..end].select_nth_unstable_by(mid - start, |&a, &b| {
+            centroids[a][axis].total_cmp(&centroids[b][axis])
+        });
+
+        let left = self.build(triangles, centroids, start, mid);
+        let right = self.build(triangles, centroids, mid, end);
+        self.nodes[node].kind = BVHNodeKind::Branch(left, right);
+        node
+    }
+
+    /// every triangle whose z range contains the plane z
+    pub fn crossing_z(&self, triangles: &[Triangle], z: f32) -> Vec<usize> {
+        let mut faces = vec![];
+        self.traverse(
+            |bbox| bbox.z_min <= z && z <= bbox.z_max,
+            |face| {
+                let tri = &triangles[face];
+                let z_min = tri[0][2].min(tri[1][2]).min(tri[2][2]);
+                let z_max = tri[0][2].max(tri[1][2]).max(tri[2][2]);
+                if z_min <= z && z <= z_max {
+                    faces.push(face);
+                }
+            }
+        );
+        faces.sort_unstable();
+        faces
+    }
+
+    /// closest point on the mesh surface to a query point
+    pub fn closest_point(
+        &self,
+        triangles: &[Triangle],
+        point: &Point3<f32>
+    ) -> Option<ClosestPoint> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut best: Option<ClosestPoint> = None;
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            let bound = box_distance_squared(&node.bbox, point);
+            if best.is_some_and(|b| bound > b.distance * b.distance) {
+                continue;
+            }
+            match node.kind {
+                BVHNodeKind::Branch(left, right) => {
+                    // visit the nearer child first so the bound tightens quickly
+                    let d_left = box_distance_squared(&self.nodes[left].bbox, point);
+                    let d_right = box_distance_squared(&self.nodes[right].bbox, point);
+                    if d_left < d_right {
+                        stack.push(right);
+                        stack.push(left);
+                    } else {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                },
+                BVHNodeKind::Leaf(start, end) => {
+                    for &face in &self.indices[start..end] {
+                        let p = closest_point_on_triangle(&triangles[face], point);
+                        let distance = (p - point).norm();
+                        if best.is_none_or(|b| distance < b.distance) {
+                            best = Some(ClosestPoint { point: p, distance, face });
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// every hit along a ray, with face indices filled in
+    pub fn ray_intersections(
+        &self,
+        triangles: &[Triangle],
+        point: &Point3<f32>,
+        ray: &Vector3<f32>
+    ) -> Vec<RayHit> {
+        let inv = Vector3::new(1. / ray[0], 1. / ray[1], 1. / ray[2]);
+        let mut hits = vec![];
+        self.traverse(
+            |bbox| ray_hits_box(bbox, point, &inv),
+            |face| {
+                if let Some(hit) = triangles[face].ray_intersection(point, ray) {
+                    hits.push(RayHit { face, ..hit });
+                }
+            }
+        );
+        hits
+    }
+
+    /// recompute node boxes bottom up after the
+    /// vertices moved but the faces did not change
+    pub fn refit(&mut self, triangles: &[Triangle]) {
+        // children are always pushed after their parent
+        for n in (0..self.nodes.len()).rev() {
+            let bbox = match self.nodes[n].kind {
+                BVHNodeKind::Branch(left, right) => {
+                    self.nodes[left].bbox.union(&self.nodes[right].bbox)
+                },
+                BVHNodeKind::Leaf(start, end) => {
+                    leaf_box(triangles, &self.indices[start..end])
+                }
+            };
+            self.nodes[n].bbox = bbox;
+        }
+    }
+
+    /// number of nodes in the tree
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// depth first walk calling ``leaf`` on every
+    /// face in nodes that pass ``accept``
+    fn traverse<A, L>(&self, accept: A, mut leaf: L)
+    where
+        A: Fn(&BoundingBox) -> bool,
+        L: FnMut(usize)
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if !accept(&node.bbox) {
+                continue;
+            }
+            match node.kind {
+                BVHNodeKind::Branch(left, right) => {
+                    stack.push(right);
+                    stack.push(left);
+                },
+                BVHNodeKind::Leaf(start, end) => {
+                    for &face in &self.indices[start..end] {
+                        leaf(face);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn box_distance_squared(bbox: &BoundingBox, point: &Point3<f32>) -> f32 {
+    let dx = (bbox.x_min - point[0]).max(point[0] - bbox.x_max).max(0.);
+    let dy = (bbox.y_min - point[1]).max(point[1] - bbox.y_max).max(0.);
+    let dz = (bbox.z_min - point[2]).max(point[2] - bbox.z_max).max(0.);
+    dx * dx + dy * dy + dz * dz
+}
+
+/// closest point on a triangle, see Ericson,
+/// Real-Time Collision Detection, section 5.1.5
+fn closest_point_on_triangle(tri: &Triangle, p: &Point3<f32>) -> Point3<f32> {
+    let a = Point3::new(tri[0][0], tri[0][1], tri[0][2]);
+    let b = Point3::new(tri[1][0], tri[1][1], tri[1][2]);
+    let c = Point3::new(tri[2][0], tri[2][1], tri[2][2]);
+    let ab = b - a;
+    let ac = c - a;
+
+    let ap = p - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0. && d2 <= 0. {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0. && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0. && d1 >= 0. && d3 <= 0. {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0. && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0. && d2 >= 0. && d6 <= 0. {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0. && (d4 - d3) >= 0. && (d5 - d6) >= 0. {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1. / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+fn leaf_box(triangles: &[Triangle], faces: &[usize]) -> BoundingBox {
+    BoundingBox::from_points(faces.iter().flat_map(|&face| triangles[face].iter()))
+        .padded(BOX_PAD)
+}
+
+/// slab test, ``inv`` is the component wise inverse of the ray direction
+fn ray_hits_box(bbox: &BoundingBox, point: &Point3<f32>, inv: &Vector3<f32>) -> bool {
+    let lo = [bbox.x_min, bbox.y_min, bbox.z_min];
+    let hi = [bbox.x_max, bbox.y_max, bbox.z_max];
+    let mut t_min = 0.0_f32;
+    let mut t_max = f32::INFINITY;
+    for d in 0..3 {
+        let t1 = (lo[d] - point[d]) * inv[d];
+        let t2 = (hi[d] - point[d]) * inv[d];
+        // NaN shows up when the origin sits exactly on a slab of an
+        // axis the ray is parallel to, f32::min/max skip it
+        t_min = t_min.max(t1.min(t2));
+        t_max = t_max.min(t1.max(t2));
+    }
+    t_min <= t_max
+}
+
+fn triangle_centroid(tri: &Triangle) -> [f32; 3] {
+    [
+        (tri[0][0] + tri[1][0] + tri[2][0]) / 3.,
+        (tri[0][1] + tri[1][1] + tri[2][1]) / 3.,
+        (tri[0][2] + tri[1][2] + tri[2][2]) / 3.
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::test_util::block;
+    use nalgebra::Point3;
+
+    #[test]
+    fn closest_point_on_block() {
+        let closest = block().closest_point(&Point3::new(5., 0., 0.)).unwrap();
+        assert!((closest.distance - 3.5).abs() < 1e-5);
+        assert!((closest.point - Point3::new(1.5, 0., 0.)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn closest_point_after_translate() {
+        let mut stl = block();
+        stl.translate(10., 0., 0.);
+        // boxes left where they were would put the block around x = 0
+        let closest = stl.closest_point(&Point3::new(15., 0., 0.)).unwrap();
+        assert!((closest.distance - 3.5).abs() < 1e-5);
+        assert!((closest.point - Point3::new(11.5, 0., 0.)).norm() < 1e-5);
+        let closest = stl.closest_point(&Point3::new(0., 0., 0.)).unwrap();
+        assert!((closest.distance - 8.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn faces_crossing_plane() {
+        let stl = block();
+        // every side of the block is two triangles
+        assert_eq!(stl.faces_crossing_z(0.).len(), 8);
+        assert!(stl.faces_crossing_z(3.).is_empty());
+    }
+}
@@ -0,0 +1,602 @@
+use super::{ExPolygon, Point, Polygon, Polyline};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// how winding numbers decide what is inside
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum FillRule {
+    EvenOdd,
+    NonZero,
+    Positive
+}
+
+impl FillRule {
+    pub fn is_inside(&self, winding: i32) -> bool {
+        match self {
+            FillRule::EvenOdd => winding % 2 != 0,
+            FillRule::NonZero => winding != 0,
+            FillRule::Positive => winding > 0
+        }
+    }
+}
+
+/// boolean operation between a subject and a clip region
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum ClipType {
+    /// subject minus clip
+    Difference,
+    Intersection,
+    Union,
+    Xor
+}
+
+impl ClipType {
+    fn is_inside(&self, subject: bool, clip: bool) -> bool {
+        match self {
+            ClipType::Difference => subject && !clip,
+            ClipType::Intersection => subject && clip,
+            ClipType::Union => subject || clip,
+            ClipType::Xor => subject != clip
+        }
+    }
+}
+
+/// boolean operation between two sets of polygons
+///
+/// The fill rule is applied to each operand on its own, so either may
+/// overlap or intersect itself. Contours in the result run counter
+/// clockwise and holes clockwise.
+pub fn boolean(
+    subject: &[Polygon],
+    clip_polygons: &[Polygon],
+    clip_type: ClipType,
+    fill_rule: FillRule
+) -> Vec<Polygon> {
+    clip(subject, clip_polygons, |a, b| {
+        clip_type.is_inside(fill_rule.is_inside(a), fill_rule.is_inside(b))
+    })
+}
+
+/// boolean operation between layer regions
+///
+/// ``ExPolygon`` keeps contours and holes oriented, so the non zero
+/// rule is used and overlapping regions in one operand are merged.
+pub fn boolean_ex(
+    subject: &[ExPolygon],
+    clip_polygons: &[ExPolygon],
+    clip_type: ClipType
+) -> Vec<ExPolygon> {
+    ExPolygon::from_polygons(boolean(
+        &to_polygons(subject),
+        &to_polygons(clip_polygons),
+        clip_type,
+        FillRule::NonZero
+    ))
+}
+
+pub fn difference(subject: &[Polygon], clip_polygons: &[Polygon], fill_rule: FillRule) -> Vec<Polygon> {
+    boolean(subject, clip_polygons, ClipType::Difference, fill_rule)
+}
+
+pub fn intersection(subject: &[Polygon], clip_polygons: &[Polygon], fill_rule: FillRule) -> Vec<Polygon> {
+    boolean(subject, clip_polygons, ClipType::Intersection, fill_rule)
+}
+
+pub fn xor(subject: &[Polygon], clip_polygons: &[Polygon], fill_rule: FillRule) -> Vec<Polygon> {
+    boolean(subject, clip_polygons, ClipType::Xor, fill_rule)
+}
+
+/// union of any number of possibly self intersecting polygons
+pub fn union(polygons: &[Polygon], fill_rule: FillRule) -> Vec<Polygon> {
+    clip(polygons, &[], |a, _| fill_rule.is_inside(a))
+}
+
+/// ``union`` grouped into contours with their holes
+pub fn union_ex(polygons: &[Polygon], fill_rule: FillRule) -> Vec<ExPolygon> {
+    ExPolygon::from_polygons(union(polygons, fill_rule))
+}
+
+/// every contour and hole as a flat list of polygons
+pub fn to_polygons(expolygons: &[ExPolygon]) -> Vec<Polygon> {
+    expolygons
+        .iter()
+        .flat_map(|x| x.polygons().cloned())
+        .collect()
+}
+
+/// the parts of polylines that lie inside the regions
+///
+/// Each segment is split where it crosses a region edge and the pieces
+/// are kept or dropped by testing their midpoint, so a polyline that
+/// leaves and re-enters a region comes back as several polylines.
+/// Closed loops, with the first point repeated at the end, are not
+/// cut at their start point.
+pub fn clip_polylines(polylines: &[Polyline], regions: &[ExPolygon]) -> Vec<Polyline> {
+    let edges: Vec<(Point, Point)> = regions
+        .iter()
+        .flat_map(|x| x.polygons())
+        .flat_map(|x| x.edges())
+        .map(|(a, b)| (*a, *b))
+        .collect();
+    let bounding_box = regions
+        .iter()
+        .map(|x| x.bounding_box())
+        .reduce(|a, b| a.union(&b));
+    let Some(bounding_box) = bounding_box else {
+        return vec![];
+    };
+    let inside = |p: &Point| bounding_box.contains(p) && regions.iter().any(|x| x.contains(p));
+
+    let mut clipped = vec![];
+    for polyline in polylines {
+        let first = clipped.len();
+        let mut current: Vec<Point> = vec![];
+        for segment in polyline.points.windows(2) {
+            let (a, b) = (segment[0], segment[1]);
+            let d = b - a;
+            let mut ts = vec![0., 1.];
+            for (c, e) in &edges {
+                let f = e - c;
+                let denom = d.x * f.y - d.y * f.x;
+                if denom == 0. {
+                    continue;
+                }
+                let ac = c - a;
+                let t = (ac.x * f.y - ac.y * f.x) / denom;
+                let u = (ac.x * d.y - ac.y * d.x) / denom;
+                if t > 0. && t < 1. && (0. ..=1.).contains(&u) {
+                    ts.push(t);
+                }
+            }
+            ts.sort_by(|a, b| a.total_cmp(b));
+
+            for t in ts.windows(2) {
+                let (p, q) = (a + d * t[0], a + d * t[1]);
+                if inside(&nalgebra::center(&p, &q)) {
+                    if current.last() != Some(&p) {
+                        if current.len() >= 2 {
+                            clipped.push(Polyline::new(std::mem::take(&mut current)));
+                        }
+                        current = vec![p];
+                    }
+                    current.push(q);
+                } else if current.len() >= 2 {
+                    clipped.push(Polyline::new(std::mem::take(&mut current)));
+                } else {
+                    current.clear();
+                }
+            }
+        }
+        if current.len() >= 2 {
+            // a closed loop cut open at its start continues in its first piece
+            let closed = polyline.points.first() == polyline.points.last();
+            let wraps = clipped.get(first).is_some_and(|x: &Polyline| x.points[0] == polyline.points[0]);
+            if closed && wraps && current.last() == polyline.points.last() {
+                current.extend_from_slice(&clipped[first].points[1..]);
+                clipped[first] = Polyline::new(current);
+            } else {
+                clipped.push(Polyline::new(current));
+            }
+        }
+    }
+    clipped
+}
+
+/// non vertical input edge, stored left to right
+struct ClipEdge {
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+    /// +1 when the polygon runs left to right along this edge
+    wind: i32,
+    /// 0 for the subject, 1 for the clip polygons
+    operand: usize
+}
+
+impl ClipEdge {
+    /// y on the edge at ``x``, exact at the endpoints and otherwise
+    /// always computed from the same data so every caller asking
+    /// about the same edge and x gets the same bits
+    fn y_at(&self, x: f64) -> f64 {
+        if x == self.x0 {
+            self.y0
+        } else if x == self.x1 {
+            self.y1
+        } else {
+            self.y0 + (self.y1 - self.y0) * (x - self.x0) / (self.x1 - self.x0)
+        }
+    }
+}
+
+/// piece of the result boundary, with the result on its left
+struct Piece {
+    from: (usize, f64),
+    to: (usize, f64)
+}
+
+/// boolean combination of two sets of polygons
+///
+/// The plane is cut into vertical slabs at every vertex and every edge
+/// crossing, so inside a slab no two edges cross and they can be ordered
+/// bottom to top. Walking up a slab while summing the winding of each
+/// operand tells where ``inside`` switches, which gives the non
+/// vertical parts of the result boundary. Comparing the inside intervals
+/// on both sides of every slab line gives the vertical parts. Points on
+/// a slab line are snapped together within a small tolerance, so the
+/// pieces can then be joined into loops by exact lookups.
+fn clip<F>(subject: &[Polygon], clip: &[Polygon], inside: F) -> Vec<Polygon>
+where
+    F: Fn(i32, i32) -> bool
+{
+    let mut edges = vec![];
+    let mut scale = 1.0_f64;
+    for (operand, polygons) in [subject, clip].iter().enumerate() {
+        for polygon in polygons.iter() {
+            for (a, b) in polygon.edges() {
+                let (ax, ay, bx, by) = (a.x as f64, a.y as f64, b.x as f64, b.y as f64);
+                scale = scale.max(ax.abs()).max(ay.abs());
+                // vertical edges never cross a vertical line through
+                // the inside of a slab so they add no winding
+                if ax == bx {
+                    continue;
+                }
+                let edge = if ax < bx {
+                    ClipEdge { x0: ax, y0: ay, x1: bx, y1: by, wind: 1, operand }
+                } else {
+                    ClipEdge { x0: bx, y0: by, x1: ax, y1: ay, wind: -1, operand }
+                };
+                edges.push(edge);
+            }
+        }
+    }
+    if edges.is_empty() {
+        return vec![];
+    }
+    let eps = 1e-10 * scale;
+
+    let mut xs: Vec<f64> = edges.iter().flat_map(|e| [e.x0, e.x1]).collect();
+    xs.sort_by(|a, b| a.total_cmp(b));
+    xs.dedup();
+    edges.sort_by(|a, b| a.x0.total_cmp(&b.x0));
+
+    let mut lines = vec![xs[0]];
+    let mut line_ys: Vec<Vec<f64>> = vec![vec![]];
+    let mut left_intervals: Vec<Vec<(f64, f64)>> = vec![vec![]];
+    let mut right_intervals: Vec<Vec<(f64, f64)>> = vec![vec![]];
+    let mut pieces = vec![];
+
+    let mut active: Vec<usize> = vec![];
+    let mut next = 0;
+    for slab in xs.windows(2) {
+        let (xa, xb) = (slab[0], slab[1]);
+        while next < edges.len() && edges[next].x0 <= xa {
+            active.push(next);
+            next += 1;
+        }
+        active.retain(|&e| edges[e].x1 > xa);
+
+        // every pair of edges whose order swaps across
+        // the slab crosses once somewhere inside it
+        let mut order = active.clone();
+        order.sort_by(|&a, &b| {
+            edges[a].y_at(xa).total_cmp(&edges[b].y_at(xa))
+                .then(edges[a].y_at(xb).total_cmp(&edges[b].y_at(xb)))
+        });
+        let mut bounds = vec![xa, xb];
+        for i in 1..order.len() {
+            let mut j = i;
+            while j > 0 && edges[order[j - 1]].y_at(xb) > edges[order[j]].y_at(xb) + eps {
+                let (e, f) = (&edges[order[j - 1]], &edges[order[j]]);
+                let dl = e.y_at(xa) - f.y_at(xa);
+                let dr = e.y_at(xb) - f.y_at(xb);
+                let x = xa + dl / (dl - dr) * (xb - xa);
+                if x > xa + eps && x < xb - eps {
+                    bounds.push(x);
+                }
+                order.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+        bounds.sort_by(|a, b| a.total_cmp(b));
+        bounds.dedup_by(|a, b| *a - *b <= eps);
+        // keep the slab end exact after the merge above
+        *bounds.last_mut().unwrap() = xb;
+
+        for sub in bounds.windows(2) {
+            let (x0, x1) = (sub[0], sub[1]);
+            let l = lines.len() - 1;
+            lines.push(x1);
+            line_ys.push(vec![]);
+            left_intervals.push(vec![]);
+            right_intervals.push(vec![]);
+            let r = lines.len() - 1;
+
+            let mut order: Vec<(f64, f64, usize)> = active
+                .iter()
+                .map(|&e| (edges[e].y_at(x0), edges[e].y_at(x1), e))
+                .collect();
+            order.sort_by(|a, b| (a.0 + a.1).total_cmp(&(b.0 + b.1)));
+
+            let mut winding = [0, 0];
+            let mut was_inside = inside(0, 0);
+            let mut start = (0., 0.);
+            let mut i = 0;
+            while i < order.len() {
+                // coincident edges act as a single boundary
+                let (yl, yr, _) = order[i];
+                while i < order.len() &&
+                      (order[i].0 - yl).abs() <= eps &&
+                      (order[i].1 - yr).abs() <= eps {
+                    let e = &edges[order[i].2];
+                    winding[e.operand] += e.wind;
+                    i += 1;
+                }
+                let is_inside = inside(winding[0], winding[1]);
+                if is_inside == was_inside {
+                    continue;
+                }
+                line_ys[l].push(yl);
+                line_ys[r].push(yr);
+                if is_inside {
+                    pieces.push(Piece { from: (l, yl), to: (r, yr) });
+                    start = (yl, yr);
+                } else {
+                    pieces.push(Piece { from: (r, yr), to: (l, yl) });
+                    right_intervals[l].push((start.0, yl));
+                    left_intervals[r].push((start.1, yr));
+                }
+                was_inside = is_inside;
+            }
+        }
+    }
+
+    // snap nearly equal ys on every line together
+    let clusters: Vec<Vec<f64>> = line_ys
+        .into_iter()
+        .map(|mut ys| {
+            ys.sort_by(|a, b| a.total_cmp(b));
+            ys.dedup_by(|a, b| *a - *b <= eps);
+            ys
+        })
+        .collect();
+    let snap = |line: usize, y: f64| -> usize {
+        let ys = &clusters[line];
+        let i = ys.partition_point(|&c| c < y);
+        if i == 0 {
+            return 0;
+        }
+        if i == ys.len() || (y - ys[i - 1]).abs() <= (ys[i] - y).abs() {
+            i - 1
+        } else {
+            i
+        }
+    };
+
+    let mut links: Vec<((usize, usize), (usize, usize))> = pieces
+        .iter()
+        .map(|p| ((p.from.0, snap(p.from.0, p.from.1)), (p.to.0, snap(p.to.0, p.to.1))))
+        .collect();
+
+    // vertical pieces where the inside differs across a line
+    for line in 0..lines.len() {
+        let snap_all = |intervals: &[(f64, f64)]| -> Vec<(usize, usize)> {
+            intervals
+                .iter()
+                .map(|&(a, b)| (snap(line, a), snap(line, b)))
+                .filter(|(a, b)| a < b)
+                .collect()
+        };
+        let left = snap_all(&left_intervals[line]);
+        let right = snap_all(&right_intervals[line]);
+        let mut cuts: Vec<usize> = left.iter().chain(right.iter())
+            .flat_map(|&(a, b)| [a, b])
+            .collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        for cut in cuts.windows(2) {
+            let covers = |x: &[(usize, usize)]| x.iter().any(|&(a, b)| a <= cut[0] && cut[1] <= b);
+            match (covers(&left), covers(&right)) {
+                (true, false) => links.push(((line, cut[0]), (line, cut[1]))),
+                (false, true) => links.push(((line, cut[1]), (line, cut[0]))),
+                _ => {}
+            }
+        }
+    }
+
+    let point = |(line, n): (usize, usize)| (lines[line], clusters[line][n]);
+    trace(&links, point)
+        .into_iter()
+        .filter_map(|loop_points| {
+            let points: Vec<Point> = remove_collinear(loop_points, eps)
+                .into_iter()
+                .map(|(x, y)| Point::new(x as f32, y as f32))
+                .collect();
+            let mut polygon = Polygon::new(points);
+            polygon.points.dedup();
+            while polygon.len() > 1 && polygon.points.first() == polygon.points.last() {
+                polygon.points.pop();
+            }
+            (polygon.len() >= 3 && polygon.area() > 0.).then_some(polygon)
+        })
+        .collect()
+}
+
+/// drop points that sit on a straight run between their neighbours,
+/// which is where the slab lines cut through the result edges
+fn remove_collinear(points: Vec<(f64, f64)>, eps: f64) -> Vec<(f64, f64)> {
+    // b can go when it is within eps of the line a c and lies between them
+    let redundant = |a: (f64, f64), b: (f64, f64), c: (f64, f64)| {
+        let (ux, uy) = (b.0 - a.0, b.1 - a.1);
+        let (wx, wy) = (c.0 - a.0, c.1 - a.1);
+        let len = (wx * wx + wy * wy).sqrt();
+        if len == 0. || (ux == 0. && uy == 0.) {
+            return true;
+        }
+        let cross = ux * wy - uy * wx;
+        let dot = ux * wx + uy * wy;
+        cross.abs() <= eps * len && dot > 0. && dot < len * len
+    };
+
+    let mut kept: Vec<(f64, f64)> = vec![];
+    for p in points {
+        while kept.len() >= 2 && redundant(kept[kept.len() - 2], kept[kept.len() - 1], p) {
+            kept.pop();
+        }
+        kept.push(p);
+    }
+    // the same check across the point where the loop closes
+    loop {
+        let n = kept.len();
+        if n < 3 {
+            return kept;
+        }
+        if redundant(kept[n - 2], kept[n - 1], kept[0]) {
+            kept.pop();
+        } else if redundant(kept[n - 1], kept[0], kept[1]) {
+            kept.remove(0);
+        } else {
+            return kept;
+        }
+    }
+}
+
+/// join directed pieces into closed loops
+///
+/// Where several pieces leave the same point the sharpest left turn
+/// is taken, which keeps regions that only touch at a corner apart.
+fn trace<K, P>(links: &[(K, K)], point: P) -> Vec<Vec<(f64, f64)>>
+where
+    K: Copy + Eq + std::hash::Hash,
+    P: Fn(K) -> (f64, f64)
+{
+    let mut outgoing: HashMap<K, Vec<usize>> = HashMap::new();
+    for (n, link) in links.iter().enumerate() {
+        if link.0 != link.1 {
+            outgoing.entry(link.0).or_default().push(n);
+        }
+    }
+
+    let mut used = vec![false; links.len()];
+    let mut loops = vec![];
+    for first in 0..links.len() {
+        if used[first] || links[first].0 == links[first].1 {
+            continue;
+        }
+        used[first] = true;
+        let start = links[first].0;
+        let mut current = first;
+        let mut points = vec![point(start)];
+        loop {
+            let (from, to) = links[current];
+            if to == start {
+                break;
+            }
+            let (fx, fy) = point(from);
+            let (tx, ty) = point(to);
+            let (dx, dy) = (tx - fx, ty - fy);
+            points.push((tx, ty));
+
+            let next = outgoing
+                .get(&to)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|&n| !used[n])
+                .max_by(|&a, &b| {
+                    let turn = |n: usize| {
+                        let (nx, ny) = point(links[n].1);
+                        let (ex, ey) = (nx - tx, ny - ty);
+                        let angle = (dx * ey - dy * ex).atan2(dx * ex + dy * ey);
+                        // turning straight back is the last resort
+                        if angle == std::f64::consts::PI { -angle } else { angle }
+                    };
+                    turn(a).total_cmp(&turn(b))
+                });
+            match next {
+                Some(n) => {
+                    used[n] = true;
+                    current = n;
+                },
+                // can only happen if the snapping broke the topology
+                None => break
+            }
+        }
+        loops.push(points);
+    }
+    loops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::test_util::{ring, square};
+
+    /// holes run clockwise and take their area away
+    fn area(polygons: &[Polygon]) -> f32 {
+        polygons.iter().map(|x| x.signed_area()).sum()
+    }
+
+    #[test]
+    fn overlapping_squares() {
+        let (a, b) = ([square(0., 0., 2.)], [square(1., 1., 2.)]);
+        for (clip_type, expected) in [
+            (ClipType::Union, 7.),
+            (ClipType::Intersection, 1.),
+            (ClipType::Difference, 3.),
+            (ClipType::Xor, 6.)
+        ] {
+            let result = boolean(&a, &b, clip_type, FillRule::NonZero);
+            assert!((area(&result) - expected).abs() < 1e-4, "{:?} gave {:?}", clip_type, result);
+        }
+    }
+
+    #[test]
+    fn ring_keeps_its_hole() {
+        let result = boolean_ex(&[ring()], &[], ClipType::Union);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].holes.len(), 1);
+        assert!((result[0].area() - 84.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn diamond_and_square() {
+        let diamond = Polygon::new(vec![
+            Point::new(1.5, 0.),
+            Point::new(0., 1.5),
+            Point::new(-1.5, 0.),
+            Point::new(0., -1.5)
+        ]);
+        let result = intersection(&[diamond], &[square(-1., -1., 2.)], FillRule::NonZero);
+        assert!((area(&result) - 3.5).abs() < 1e-4, "got {:?}", result);
+    }
+
+    #[test]
+    fn corner_touching_squares_stay_apart() {
+        let result = union(&[square(0., 0., 1.), square(1., 1., 1.)], FillRule::NonZero);
+        assert_eq!(result.len(), 2);
+        assert!((area(&result) - 2.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fill_rules() {
+        let overlapping = [square(0., 0., 2.), square(1., 1., 2.)];
+        assert!((area(&union(&overlapping, FillRule::EvenOdd)) - 6.).abs() < 1e-4);
+        assert!((area(&union(&overlapping, FillRule::Positive)) - 7.).abs() < 1e-4);
+
+        let mut clockwise = square(0., 0., 2.);
+        clockwise.make_clockwise();
+        assert!((area(&union(std::slice::from_ref(&clockwise), FillRule::NonZero)) - 4.).abs() < 1e-4);
+        assert!(union(&[clockwise], FillRule::Positive).is_empty());
+    }
+
+    #[test]
+    fn polylines_split_around_hole() {
+        let line = Polyline::new(vec![Point::new(-1., 5.), Point::new(11., 5.)]);
+        let result = clip_polylines(&[line], &[ring()]);
+        assert_eq!(result.len(), 2);
+        for piece in &result {
+            assert!((piece.length() - 3.).abs() < 1e-4, "got {:?}", piece);
+        }
+    }
+}
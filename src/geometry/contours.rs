@@ -0,0 +1,206 @@
+use super::{EdgeKey, Point, Polygon, Polyline, SliceSegment};
+use std::collections::HashMap;
+
+/// chains of points built from the segments of one layer
+#[derive(Clone, Debug, Default)]
+pub struct Contours {
+    /// closed loops
+    pub closed: Vec<Polygon>,
+    /// chains that could not be closed, which means the
+    /// mesh has holes or non manifold edges at this layer
+    pub open: Vec<Polyline>
+}
+
+/// join the segments of a layer into loops using mesh topology
+///
+/// Two segments connect when they cross the same mesh edge, the end
+/// edge of one being the start edge of the next. Nothing is compared
+/// by distance, so there are no tolerances to tune and any number of
+/// islands and holes come out as separate loops. Segments from faces
+/// with flipped winding are still picked up, just walked backwards.
+pub fn stitch(segments: &[SliceSegment]) -> Contours {
+    let mut by_start: HashMap<EdgeKey, Vec<usize>> = HashMap::new();
+    let mut by_end: HashMap<EdgeKey, Vec<usize>> = HashMap::new();
+    for (n, seg) in segments.iter().enumerate() {
+        by_start.entry(seg.start_edge).or_default().push(n);
+        by_end.entry(seg.end_edge).or_default().push(n);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut contours = Contours::default();
+
+    for first in 0..segments.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+
+        let mut chain = vec![segments[first].start];
+        push_point(&mut chain, segments[first].end);
+        let start_edge = segments[first].start_edge;
+        let mut edge = segments[first].end_edge;
+        let mut closed = false;
+
+        // walk forward until we are back where we started or stuck
+        while let Some((next, flipped)) = next_segment(
+            &by_start, &by_end, &used, edge
+        ) {
+            used[next] = true;
+            let seg = &segments[next];
+            let (point, next_edge) = if flipped {
+                (seg.start, seg.start_edge)
+            } else {
+                (seg.end, seg.end_edge)
+            };
+            push_point(&mut chain, point);
+            edge = next_edge;
+            if edge == start_edge {
+                closed = true;
+                break;
+            }
+        }
+
+        if closed {
+            // the walk ends on the first point again
+            if chain.len() > 1 && chain.first() == chain.last() {
+                chain.pop();
+            }
+            if chain.len() >= 3 {
+                contours.closed.push(Polygon::new(to_points(chain)));
+            }
+            continue;
+        }
+
+        // dead end, so also walk backwards from the first
+        // segment to report the whole open chain
+        let mut edge = start_edge;
+        let mut head = vec![];
+        while let Some((prev, flipped)) = next_segment(
+            &by_end, &by_start, &used, edge
+        ) {
+            used[prev] = true;
+            let seg = &segments[prev];
+            let (point, prev_edge) = if flipped {
+                (seg.end, seg.end_edge)
+            } else {
+                (seg.start, seg.start_edge)
+            };
+            head.push(point);
+            edge = prev_edge;
+        }
+        head.reverse();
+        let mut open = vec![];
+        for point in head.into_iter().chain(chain) {
+            push_point(&mut open, point);
+        }
+        contours.open.push(Polyline::new(to_points(open)));
+    }
+    contours
+}
+
+/// an unused segment continuing from ``edge``, preferring one whose
+/// winding agrees (found in ``forward``) over a flipped one
+fn next_segment(
+    forward: &HashMap<EdgeKey, Vec<usize>>,
+    backward: &HashMap<EdgeKey, Vec<usize>>,
+    used: &[bool],
+    edge: EdgeKey
+) -> Option<(usize, bool)> {
+    let unused = |map: &HashMap<EdgeKey, Vec<usize>>| {
+        map.get(&edge)
+            .and_then(|x| x.iter().copied().find(|&n| !used[n]))
+    };
+    if let Some(n) = unused(forward) {
+        return Some((n, false));
+    }
+    unused(backward).map(|n| (n, true))
+}
+
+/// append a point unless it repeats the last one, which happens
+/// when the plane passes exactly through a mesh vertex
+fn push_point(chain: &mut Vec<[f32; 2]>, point: [f32; 2]) {
+    if chain.last() != Some(&point) {
+        chain.push(point);
+    }
+}
+
+fn to_points(chain: Vec<[f32; 2]>) -> Vec<Point> {
+    chain
+        .into_iter()
+        .map(|x| Point::new(x[0], x[1]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::test_util::block;
+
+    /// segments around a square, each point on its own made up edge
+    fn square_segments(x: f32, y: f32, first_edge: usize) -> Vec<SliceSegment> {
+        let points = [[x, y], [x + 1., y], [x + 1., y + 1.], [x, y + 1.]];
+        let edge = |k: usize| [first_edge + k % 4, first_edge + 100];
+        (0..4)
+            .map(|k| SliceSegment {
+                start: points[k],
+                end: points[(k + 1) % 4],
+                start_edge: edge(k),
+                end_edge: edge(k + 1),
+                face: first_edge + k
+            })
+            .collect()
+    }
+
+    #[test]
+    fn block_contours() {
+        let stl = block();
+        let counts: Vec<usize> = stl.plane_sweep(&[-2.5, 0., 2.5])
+            .iter()
+            .map(|x| {
+                let contours = stitch(x);
+                assert!(contours.open.is_empty());
+                contours.closed.len()
+            })
+            .collect();
+        assert_eq!(counts, [0, 1, 1]);
+
+        let contours = stitch(&stl.plane_sweep(&[0.])[0]);
+        let contour = &contours.closed[0];
+        assert!(contour.is_counter_clockwise());
+        assert!((contour.area() - 12.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn separate_loops() {
+        let mut segments = square_segments(0., 0., 0);
+        segments.extend(square_segments(5., 0., 10));
+        segments.swap(1, 6);
+        let contours = stitch(&segments);
+        assert_eq!(contours.closed.len(), 2);
+        assert!(contours.closed.iter().all(|x| x.len() == 4));
+    }
+
+    #[test]
+    fn flipped_segment_still_closes() {
+        let mut segments = square_segments(0., 0., 0);
+        let seg = &mut segments[2];
+        (seg.start, seg.end) = (seg.end, seg.start);
+        (seg.start_edge, seg.end_edge) = (seg.end_edge, seg.start_edge);
+        let contours = stitch(&segments);
+        assert!(contours.open.is_empty());
+        assert_eq!(contours.closed.len(), 1);
+        assert_eq!(contours.closed[0].len(), 4);
+    }
+
+    #[test]
+    fn gap_gives_open_chain() {
+        let mut segments = square_segments(0., 0., 0);
+        segments.remove(2);
+        // start the walk in the middle so it has to go both ways
+        segments.swap(0, 1);
+        let contours = stitch(&segments);
+        assert!(contours.closed.is_empty());
+        assert_eq!(contours.open.len(), 1);
+        assert_eq!(contours.open[0].points.len(), 4);
+    }
+}
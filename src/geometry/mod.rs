@@ -1,3 +1,19 @@
+pub mod bvh;
+pub mod clipping;
+pub mod contours;
+pub mod offset;
+pub mod plane_sweep;
+pub mod polygon;
+#[cfg(test)]
+pub(crate) mod test_util;
+
+pub use bvh::{BVH, ClosestPoint};
+pub use clipping::{ClipType, FillRule};
+pub use contours::Contours;
+pub use offset::JoinType;
+pub use plane_sweep::{EdgeKey, SliceSegment};
+pub use polygon::{BoundingBox2D, ExPolygon, Orientation, Point, Polygon, Polyline};
+
 use nalgebra;
 use std::env;
 use std::fs::OpenOptions;
@@ -18,20 +34,138 @@ type Faces = Vec<Face>;
 type Triangles = Vec<Triangle>;
 type Vertices = Vec<Vertex>;
 
+/// tolerance used to decide whether a ray grazes a triangle
+/// edge or vertex, in barycentric units
+const RAY_EDGE_TOL: f64 = 1e-9;
+
+/// candidate directions for parity based inside/outside queries
+/// the first is the historical +x ray, the rest are deliberately
+/// irrational-ish so they rarely line up with model features
+const INSIDE_RAYS: [[f32; 3]; 4] = [
+    [1., 0., 0.],
+    [0.577_350_3, 0.577_350_3, 0.577_350_3],
+    [-0.267_261_24, 0.534_522_5, 0.801_783_7],
+    [0.301_511_35, -0.904_534_06, 0.301_511_35]
+];
+
+/// where on a triangle a ray landed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HitLocation {
+    Interior,
+    Edge,
+    Vertex
+}
+
+/// hit record for a ray/triangle intersection
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    /// distance along the ray, in units of the ray length
+    pub distance: f32,
+    /// barycentric weights of the hit point for
+    /// the triangle vertices 0, 1 and 2
+    pub barycentric: [f32; 3],
+    /// index of the face in the owning mesh
+    pub face: usize,
+    /// whether the ray hit the interior, an edge or a vertex
+    pub location: HitLocation
+}
+
+impl RayHit {
+    /// hit point in space given the ray used to generate this hit
+    pub fn point(
+        &self,
+        point: &nalgebra::Point3::<f32>,
+        ray: &nalgebra::Vector3::<f32>
+    ) -> nalgebra::Point3::<f32> {
+        point + ray * self.distance
+    }
+}
+
 pub trait RayIntersection {
+    /// Möller–Trumbore ray/triangle intersection
+    ///
+    /// Only hits in front of the ray origin (distance >= 0) are
+    /// reported. Hits on edges and vertices are included and flagged
+    /// through ``HitLocation`` so callers that count crossings can
+    /// decide what to do with them. The face index is left at 0 and
+    /// is filled in by the mesh level queries.
     fn ray_intersection(
-        &self, 
+        &self,
         point: &nalgebra::Point3::<f32>,
         ray: &nalgebra::Vector3::<f32>
-    ) -> bool {
-        true // todo
-    }
+    ) -> Option<RayHit>;
 }
 
-impl RayIntersection for Triangle {}
+impl RayIntersection for Triangle {
+    fn ray_intersection(
+        &self,
+        point: &nalgebra::Point3::<f32>,
+        ray: &nalgebra::Vector3::<f32>
+    ) -> Option<RayHit> {
+        // work in f64, single precision is not enough for the
+        // determinant on thin triangles
+        let v = |i: usize| nalgebra::Vector3::<f64>::new(
+            self[i][0] as f64, self[i][1] as f64, self[i][2] as f64
+        );
+        let (v0, v1, v2) = (v(0), v(1), v(2));
+        let orig = nalgebra::Vector3::<f64>::new(
+            point[0] as f64, point[1] as f64, point[2] as f64
+        );
+        let dir = nalgebra::Vector3::<f64>::new(
+            ray[0] as f64, ray[1] as f64, ray[2] as f64
+        );
+
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+        let p = dir.cross(&e2);
+        let det = e1.dot(&p);
+
+        // ray parallel to the triangle plane, scale the
+        // tolerance by the triangle size
+        if det.abs() <= f64::EPSILON * e1.norm() * e2.norm() * dir.norm() {
+            return None;
+        }
+
+        let inv_det = 1. / det;
+        let t_vec = orig - v0;
+        let u = t_vec.dot(&p) * inv_det;
+        if !(-RAY_EDGE_TOL..=1. + RAY_EDGE_TOL).contains(&u) {
+            return None;
+        }
+
+        let q = t_vec.cross(&e1);
+        let w = dir.dot(&q) * inv_det;
+        if w < -RAY_EDGE_TOL || u + w > 1. + RAY_EDGE_TOL {
+            return None;
+        }
+
+        let t = e2.dot(&q) * inv_det;
+        if t < 0. {
+            return None;
+        }
+
+        let bary = [1. - u - w, u, w];
+        let n_on_edge = bary
+            .iter()
+            .filter(|b| b.abs() <= RAY_EDGE_TOL)
+            .count();
+        let location = match n_on_edge {
+            0 => HitLocation::Interior,
+            1 => HitLocation::Edge,
+            _ => HitLocation::Vertex
+        };
 
-///
-#[derive(Debug)]
+        Some(RayHit {
+            distance: t as f32,
+            barycentric: [bary[0] as f32, bary[1] as f32, bary[2] as f32],
+            face: 0,
+            location
+        })
+    }
+}
+
+/// axis aligned bounding box
+#[derive(Clone, Copy, Debug)]
 pub struct BoundingBox {
     pub x_min: f32,
     pub y_min: f32,
@@ -41,9 +175,82 @@ pub struct BoundingBox {
     pub z_max: f32
 }
 
-///
+impl BoundingBox {
+    /// tightest box around a set of points, an empty set gives an
+    /// inverted box that acts as the identity for ``union``
+    pub fn from_points<'a, I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Vertex>
+    {
+        let mut bb = BoundingBox::empty();
+        for p in points {
+            bb.x_min = bb.x_min.min(p[0]);
+            bb.y_min = bb.y_min.min(p[1]);
+            bb.z_min = bb.z_min.min(p[2]);
+            bb.x_max = bb.x_max.max(p[0]);
+            bb.y_max = bb.y_max.max(p[1]);
+            bb.z_max = bb.z_max.max(p[2]);
+        }
+        bb
+    }
+
+    pub fn empty() -> Self {
+        BoundingBox {
+            x_min: f32::MAX,
+            y_min: f32::MAX,
+            z_min: f32::MAX,
+            x_max: f32::MIN,
+            y_max: f32::MIN,
+            z_max: f32::MIN
+        }
+    }
+
+    pub fn center(&self) -> nalgebra::Point3::<f32> {
+        nalgebra::Point3::<f32>::new(
+            0.5 * (self.x_min + self.x_max),
+            0.5 * (self.y_min + self.y_max),
+            0.5 * (self.z_min + self.z_max)
+        )
+    }
+
+    /// grow the box by ``pad`` in every direction
+    pub fn padded(&self, pad: f32) -> Self {
+        BoundingBox {
+            x_min: self.x_min - pad,
+            y_min: self.y_min - pad,
+            z_min: self.z_min - pad,
+            x_max: self.x_max + pad,
+            y_max: self.y_max + pad,
+            z_max: self.z_max + pad
+        }
+    }
+
+    /// extent along each axis
+    pub fn size(&self) -> nalgebra::Vector3::<f32> {
+        nalgebra::Vector3::<f32>::new(
+            self.x_max - self.x_min,
+            self.y_max - self.y_min,
+            self.z_max - self.z_min
+        )
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> Self {
+        BoundingBox {
+            x_min: self.x_min.min(other.x_min),
+            y_min: self.y_min.min(other.y_min),
+            z_min: self.z_min.min(other.z_min),
+            x_max: self.x_max.max(other.x_max),
+            y_max: self.y_max.max(other.y_max),
+            z_max: self.z_max.max(other.z_max)
+        }
+    }
+}
+
+/// triangle mesh read from an STL file
 #[derive(Clone, Debug)]
 pub struct STLMesh {
+    bounding_box: BoundingBox,
+    bvh: BVH,
     faces: Faces,
     file_name: String,
     triangles: Triangles,
@@ -56,43 +263,43 @@ impl STLMesh {
             .read(true)
             .open(&file_name)
             // .unwrap();
-            .expect(
-                format!(
-                    "Failed to open STL file. Path is {:?} and current dir is {:?}", 
-                    path::Path::new(&file_name),
-                    env::current_dir().unwrap()
-                ).as_str()
-            );
+            .unwrap_or_else(|_| panic!(
+                "Failed to open STL file. Path is {:?} and current dir is {:?}", 
+                path::Path::new(&file_name),
+                env::current_dir().unwrap()
+            ));
         let stl = stl_io::read_stl(&mut file).unwrap();
 
         // let triangles = STLMesh::_triangles(&stl);
         let triangles = STLMesh::_triangles(&stl.faces, &stl.vertices);
+        let bvh = BVH::new(&triangles);
         STLMesh {
+            bounding_box: BoundingBox::from_points(&stl.vertices),
+            bvh,
             faces: stl.faces,
-            file_name: file_name,
-            triangles: triangles,
+            file_name,
+            triangles,
             vertices: stl.vertices
         }
     }
 
+    /// per axis bounding box, cached and kept current by the transforms
     pub fn bounding_box(&self) -> BoundingBox {
-        let x_min = self.vertices()
-            .iter()
-            .min_by(|a, b| a.partial_cmp(&b).unwrap())
-            .unwrap();
-        let x_max = self.vertices()
-            .iter()
-            .max_by(|a, b| a.partial_cmp(&b).unwrap())
-            .unwrap();
+        self.bounding_box
+    }
 
-        BoundingBox {
-            x_min: x_min[0], 
-            y_min: x_min[1],
-            z_min: x_min[2],
-            x_max: x_max[0],
-            y_max: x_max[1],
-            z_max: x_max[2]
-        }
+    pub fn bvh(&self) -> &BVH {
+        &self.bvh
+    }
+
+    /// closest point on the surface to a query point
+    pub fn closest_point(&self, point: &nalgebra::Point3::<f32>) -> Option<ClosestPoint> {
+        self.bvh.closest_point(&self.triangles, point)
+    }
+
+    /// indices of the faces whose z range contains the plane z
+    pub fn faces_crossing_z(&self, z: f32) -> Vec<usize> {
+        self.bvh.crossing_z(&self.triangles, z)
     }
 
     pub fn faces(&self) -> &Vec<IndexedTriangle> {
@@ -103,26 +310,93 @@ impl STLMesh {
         &self.file_name
     }
 
-    pub fn home_z(&mut self) -> () {
+    pub fn home_z(&mut self) {
         let bb = self.bounding_box();
         self.translate(0., 0., -bb.z_min);
     }
 
+    /// indices of the faces pointing down further than ``angle``
+    /// degrees from vertical, 0 being a wall and 90 a flat ceiling
+    ///
+    /// Normals are taken from the vertex winding, the normals stored in
+    /// the file are often left zero.
+    pub fn overhanging_faces(&self, angle: f32) -> Vec<usize> {
+        let threshold = angle.to_radians().sin();
+        self.triangles
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| {
+                let [a, b, c] = t.map(|x| nalgebra::Vector3::new(x[0], x[1], x[2]));
+                let normal = (b - a).cross(&(c - a));
+                let norm = normal.norm();
+                norm > 0. && -normal.z / norm > threshold
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// parity based point containment
+    ///
+    /// A ray is cast from the point and crossings are counted. If the
+    /// ray grazes an edge or vertex the count is ambiguous, so the next
+    /// direction is tried. If every direction is ambiguous the answer
+    /// falls back to a majority vote over all of them.
     pub fn is_inside(&self, point: nalgebra::Point3::<f32>) -> bool {
-        // arbitrary ray in +x direction
-        let ray = nalgebra::Vector3::<f32>::new(1., 0., 0.);
-        
-        let mut count = 0;
-        for tri in self.triangles() {
-            if tri.ray_intersection(&point, &ray) {
-                count = count + 1;
+        let mut votes = 0;
+        for dir in INSIDE_RAYS.iter() {
+            let ray = nalgebra::Vector3::<f32>::new(dir[0], dir[1], dir[2]);
+            let hits = self.ray_intersections(&point, &ray);
+            let inside = hits.len() % 2 == 1;
+            if hits.iter().all(|x| x.location == HitLocation::Interior) {
+                return inside;
+            }
+            if inside {
+                votes += 1;
             }
         }
+        2 * votes > INSIDE_RAYS.len()
+    }
+
+    /// closest hit along a ray if any
+    pub fn ray_cast(
+        &self,
+        point: &nalgebra::Point3::<f32>,
+        ray: &nalgebra::Vector3::<f32>
+    ) -> Option<RayHit> {
+        self.ray_intersections(point, ray)
+            .into_iter()
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    /// every hit along a ray, with face indices filled in
+    pub fn ray_intersections(
+        &self,
+        point: &nalgebra::Point3::<f32>,
+        ray: &nalgebra::Vector3::<f32>
+    ) -> Vec<RayHit> {
+        self.bvh.ray_intersections(&self.triangles, point, ray)
+    }
 
-        count % 2 == 1
+    /// segments for every plane in ``zs``, see ``plane_sweep::plane_sweep``
+    pub fn plane_sweep(&self, zs: &[f32]) -> Vec<Vec<SliceSegment>> {
+        plane_sweep::plane_sweep(self, zs)
     }
 
-    pub fn scale(&mut self, x: f32, y: f32, z: f32) -> () {
+    /// rotate ``angle`` degrees counter clockwise about the z axis
+    pub fn rotate_z(&mut self, angle: f32) {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        self.vertices = self.vertices
+            .iter_mut()
+            .map(|a| Vector::new(
+                [cos * a[0] - sin * a[1], sin * a[0] + cos * a[1], a[2]]
+            ))
+            .collect::<Vec<Vector<f32>>>();
+        self.triangles = STLMesh::_triangles(&self.faces, &self.vertices);
+        self.bounding_box = BoundingBox::from_points(&self.vertices);
+        self.bvh.refit(&self.triangles);
+    }
+
+    pub fn scale(&mut self, x: f32, y: f32, z: f32) {
         self.vertices = self.vertices
             .iter_mut()
             .map(|a| Vector::new(
@@ -130,9 +404,11 @@ impl STLMesh {
             ))
             .collect::<Vec<Vector<f32>>>();
         self.triangles = STLMesh::_triangles(&self.faces, &self.vertices);
+        self.bounding_box = BoundingBox::from_points(&self.vertices);
+        self.bvh.refit(&self.triangles);
     }
 
-    pub fn translate(&mut self, x: f32, y: f32, z: f32) -> () {
+    pub fn translate(&mut self, x: f32, y: f32, z: f32) {
         self.vertices = self.vertices
             .iter_mut()
             .map(|a| Vector::new(
@@ -140,6 +416,8 @@ impl STLMesh {
             ))
             .collect::<Vec<Vector<f32>>>();
         self.triangles = STLMesh::_triangles(&self.faces, &self.vertices);
+        self.bounding_box = BoundingBox::from_points(&self.vertices);
+        self.bvh.refit(&self.triangles);
     }
 
     // pub fn _triangles(stl: &IndexedMesh) -> Vec<[Vector<f32>; 3]> {
@@ -166,7 +444,7 @@ impl STLMesh {
         &self.vertices
     }
 
-    // pub fn write_stl(&self, file_name: &str) -> () {
+    // pub fn write_stl(&self, file_name: &str) {
     //     let mut file = OpenOptions::new()
     //         .write(true)
     //         .create_new(true)
@@ -175,3 +453,75 @@ impl STLMesh {
     //     stl_io::write_stl(&mut file, self.stl.iter()).unwrap();
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_util::block;
+    use nalgebra::{Point3, Vector3};
+
+    fn triangle() -> Triangle {
+        [
+            Vector::new([0., 0., 0.]),
+            Vector::new([1., 0., 0.]),
+            Vector::new([0., 1., 0.])
+        ]
+    }
+
+    #[test]
+    fn ray_hits_triangle_interior() {
+        let hit = triangle()
+            .ray_intersection(&Point3::new(0.25, 0.25, 1.), &Vector3::new(0., 0., -1.))
+            .unwrap();
+        assert_eq!(hit.location, HitLocation::Interior);
+        assert!((hit.distance - 1.).abs() < 1e-6);
+        assert!((hit.barycentric[0] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ray_misses_triangle() {
+        let down = Vector3::new(0., 0., -1.);
+        assert!(triangle().ray_intersection(&Point3::new(1., 1., 1.), &down).is_none());
+        // hits behind the origin of the ray do not count
+        assert!(triangle().ray_intersection(&Point3::new(0.25, 0.25, -1.), &down).is_none());
+        // parallel to the plane of the triangle
+        assert!(triangle().ray_intersection(&Point3::new(-1., 0.25, 0.), &Vector3::new(1., 0., 0.)).is_none());
+    }
+
+    #[test]
+    fn ray_through_edge_or_vertex() {
+        let down = Vector3::new(0., 0., -1.);
+        for (x, y, location) in [
+            (0.5, 0., HitLocation::Edge),
+            (0., 0.5, HitLocation::Edge),
+            (0.5, 0.5, HitLocation::Edge),
+            (0., 0., HitLocation::Vertex),
+            (1., 0., HitLocation::Vertex),
+            (0., 1., HitLocation::Vertex)
+        ] {
+            let hit = triangle().ray_intersection(&Point3::new(x, y, 1.), &down).unwrap();
+            assert_eq!(hit.location, location, "at ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn inside_block() {
+        let stl = block();
+        // the +x ray from the middle runs through the diagonal of a side
+        let hits = stl.ray_intersections(&Point3::origin(), &Vector3::new(1., 0., 0.));
+        assert!(hits.iter().any(|x| x.location == HitLocation::Edge));
+        assert!(stl.is_inside(Point3::origin()));
+        assert!(stl.is_inside(Point3::new(1., -1.5, 2.)));
+        assert!(!stl.is_inside(Point3::new(2., 0., 0.)));
+        assert!(!stl.is_inside(Point3::new(0., 0., 3.)));
+        assert!(!stl.is_inside(Point3::new(-5., 5., -5.)));
+    }
+
+    #[test]
+    fn ray_cast_takes_nearest_hit() {
+        let hit = block()
+            .ray_cast(&Point3::new(0., 0.5, 10.), &Vector3::new(0., 0., -1.))
+            .unwrap();
+        assert!((hit.distance - 7.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,227 @@
+use super::clipping::{self, FillRule};
+use super::{ExPolygon, Point, Polygon};
+use nalgebra::Vector2;
+use serde::Deserialize;
+
+/// largest distance a round join may stray from the true arc
+const ARC_TOLERANCE: f32 = 0.005;
+
+/// how offset edges are connected around convex corners
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum JoinType {
+    /// sharp corner, squared off once the tip would reach further
+    /// than the given multiple of the offset distance
+    Miter(f32),
+    Round,
+    Square
+}
+
+/// grow (positive ``delta``) or shrink (negative ``delta``) regions
+///
+/// Every loop is shifted along its edge normals and the corners are
+/// joined with ``join``. The raw loops intersect themselves around
+/// concave corners and where a region gets thinner than the offset,
+/// so they are cleaned up by a union with the positive fill rule,
+/// which also drops islands that vanish completely.
+pub fn offset_expolygons(expolygons: &[ExPolygon], delta: f32, join: JoinType) -> Vec<ExPolygon> {
+    if delta == 0. {
+        return expolygons.to_vec();
+    }
+    let raw: Vec<Polygon> = expolygons
+        .iter()
+        .flat_map(|x| x.polygons())
+        .filter(|x| x.len() >= 3)
+        .map(|x| offset_loop(x, delta, join))
+        .collect();
+    clipping::union_ex(&raw, FillRule::Positive)
+}
+
+/// ``offset_expolygons`` for bare polygons, taken to be oriented
+/// like contours (counter clockwise) and holes (clockwise)
+pub fn offset_polygons(polygons: &[Polygon], delta: f32, join: JoinType) -> Vec<Polygon> {
+    let raw: Vec<Polygon> = polygons
+        .iter()
+        .filter(|x| x.len() >= 3)
+        .map(|x| offset_loop(x, delta, join))
+        .collect();
+    clipping::union(&raw, FillRule::Positive)
+}
+
+/// shrink by half of ``width`` and grow back, which drops the parts of
+/// the regions thinner than ``width`` and leaves the rest in place
+pub fn open(expolygons: &[ExPolygon], width: f32, join: JoinType) -> Vec<ExPolygon> {
+    let shrunk = offset_expolygons(expolygons, -0.5 * width, join);
+    offset_expolygons(&shrunk, 0.5 * width, join)
+}
+
+/// raw offset of a single loop, self intersections included
+fn offset_loop(polygon: &Polygon, delta: f32, join: JoinType) -> Polygon {
+    let points = &polygon.points;
+    let n = points.len();
+    // unit normals pointing right of each edge, away from the filled side
+    let normals: Vec<Vector2<f32>> = (0..n)
+        .map(|i| {
+            let d = points[(i + 1) % n] - points[i];
+            let len = d.norm();
+            if len == 0. {
+                Vector2::zeros()
+            } else {
+                Vector2::new(d.y, -d.x) / len
+            }
+        })
+        .collect();
+
+    let mut out = vec![];
+    for i in 0..n {
+        let p = points[i];
+        let n1 = normals[(i + n - 1) % n];
+        let n2 = normals[i];
+        if n1 == Vector2::zeros() || n2 == Vector2::zeros() {
+            out.push(p + (n1 + n2) * delta);
+            continue;
+        }
+
+        let sin = n1.x * n2.y - n1.y * n2.x;
+        let cos = n1.dot(&n2);
+        // concave with respect to the offset direction, or straight, in
+        // which case the loop folds back here and the union cleans it up
+        if sin * delta <= 0. || cos > 0.999_999 {
+            out.push(p + n1 * delta);
+            if cos <= 0.999_999 {
+                out.push(p);
+            }
+            out.push(p + n2 * delta);
+            continue;
+        }
+
+        match join {
+            JoinType::Miter(limit) => {
+                // the miter tip sits at delta / cos(half angle) from p
+                let half_cos = ((1. + cos) / 2.).sqrt();
+                if half_cos > 0. && 1. / half_cos <= limit {
+                    out.push(p + (n1 + n2) * (delta / (1. + cos)));
+                } else {
+                    square_join(&mut out, p, n1, n2, delta);
+                }
+            },
+            JoinType::Round => round_join(&mut out, p, n1, n2, delta),
+            JoinType::Square => square_join(&mut out, p, n1, n2, delta)
+        }
+    }
+    Polygon::new(out)
+}
+
+/// arc around p from n1 to n2
+fn round_join(out: &mut Vec<Point>, p: Point, n1: Vector2<f32>, n2: Vector2<f32>, delta: f32) {
+    let radius = delta.abs();
+    let angle = (n1.x * n2.y - n1.y * n2.x).atan2(n1.dot(&n2));
+    let step = 2. * (1. - ARC_TOLERANCE.min(radius) / radius).acos();
+    let steps = ((angle.abs() / step).ceil() as usize).max(1);
+    for k in 0..=steps {
+        let a = angle * k as f32 / steps as f32;
+        let (s, c) = a.sin_cos();
+        let v = Vector2::new(n1.x * c - n1.y * s, n1.x * s + n1.y * c);
+        out.push(p + v * delta);
+    }
+}
+
+/// corner cut off at distance ``delta`` from p
+fn square_join(out: &mut Vec<Point>, p: Point, n1: Vector2<f32>, n2: Vector2<f32>, delta: f32) {
+    // edge directions are the normals turned back a quarter
+    let d1 = Vector2::new(-n1.y, n1.x);
+    let d2 = Vector2::new(-n2.y, n2.x);
+    let mid = (n1 + n2).normalize();
+    // points where the offset edges meet the line
+    // perpendicular to the bisector at distance delta
+    let reach = |n: Vector2<f32>, d: Vector2<f32>| {
+        let along = (1. - n.dot(&mid)) / d.dot(&mid);
+        p + n * delta + d * (delta * along)
+    };
+    out.push(reach(n1, d1));
+    out.push(reach(n2, d2));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::test_util::{ring, square};
+
+    fn area(expolygons: &[ExPolygon]) -> f32 {
+        expolygons.iter().map(|x| x.area()).sum()
+    }
+
+    #[test]
+    fn ring_in_and_out() {
+        let grown = offset_expolygons(&[ring()], 1., JoinType::Miter(3.));
+        assert_eq!(grown.len(), 1);
+        assert_eq!(grown[0].holes.len(), 1);
+        assert!((area(&grown) - (144. - 4.)).abs() < 1e-3, "got {:?}", grown);
+
+        let shrunk = offset_expolygons(&[ring()], -1., JoinType::Miter(3.));
+        assert_eq!(shrunk.len(), 1);
+        assert_eq!(shrunk[0].holes.len(), 1);
+        assert!((area(&shrunk) - (64. - 36.)).abs() < 1e-3, "got {:?}", shrunk);
+    }
+
+    #[test]
+    fn inset_past_the_middle_vanishes() {
+        let part = ExPolygon::new(square(0., 0., 10.), vec![]);
+        assert!(offset_expolygons(&[part], -6., JoinType::Miter(3.)).is_empty());
+    }
+
+    #[test]
+    fn dumbbell_splits() {
+        let dumbbell = Polygon::new(
+            [(0., 0.), (10., 0.), (10., 4.), (14., 4.), (14., 0.), (24., 0.),
+             (24., 10.), (14., 10.), (14., 6.), (10., 6.), (10., 10.), (0., 10.)]
+                .iter()
+                .map(|&(x, y)| Point::new(x, y))
+                .collect()
+        );
+        let part = ExPolygon::new(dumbbell, vec![]);
+        assert_eq!(offset_expolygons(std::slice::from_ref(&part), -0.5, JoinType::Miter(3.)).len(), 1);
+        let islands = offset_expolygons(&[part], -1.5, JoinType::Miter(3.));
+        assert_eq!(islands.len(), 2);
+        assert!((area(&islands) - 2. * 49.).abs() < 1e-3, "got {:?}", islands);
+    }
+
+    #[test]
+    fn join_types() {
+        // a square grown by 1 gains its sides and whatever each join
+        // puts on the four corners
+        let cut_corner = 1. - (2f32.sqrt() - 1.).powi(2);
+        for (join, corner, tolerance) in [
+            (JoinType::Miter(3.), 1., 1e-3),
+            // too sharp for the limit, squared off instead
+            (JoinType::Miter(1.), cut_corner, 1e-3),
+            (JoinType::Square, cut_corner, 1e-3),
+            // chords of the arc cut inside it by up to the arc tolerance
+            (JoinType::Round, std::f32::consts::FRAC_PI_4, 0.05)
+        ] {
+            let grown = offset_polygons(&[square(0., 0., 10.)], 1., join);
+            assert_eq!(grown.len(), 1);
+            let expected = 140. + 4. * corner;
+            assert!((grown[0].area() - expected).abs() < tolerance, "{:?} gave {}", join, grown[0].area());
+        }
+    }
+
+    #[test]
+    fn open_drops_slivers() {
+        let spur = Polygon::new(
+            [(0., 0.), (10., 0.), (10., 4.85), (15., 4.85), (15., 5.15), (10., 5.15), (10., 10.), (0., 10.)]
+                .iter()
+                .map(|&(x, y)| Point::new(x, y))
+                .collect()
+        );
+        let sliver = Polygon::new(
+            [(20., 0.), (20.3, 0.), (20.3, 10.), (20., 10.)]
+                .iter()
+                .map(|&(x, y)| Point::new(x, y))
+                .collect()
+        );
+        let parts = [ExPolygon::new(spur, vec![]), ExPolygon::new(sliver, vec![])];
+        let opened = open(&parts, 0.4, JoinType::Miter(3.));
+        assert_eq!(opened.len(), 1);
+        assert!((area(&opened) - 100.).abs() < 1e-3, "got {:?}", opened);
+    }
+}
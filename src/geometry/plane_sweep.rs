@@ -0,0 +1,168 @@
+use super::STLMesh;
+
+/// mesh edge given by its two vertex indices, smallest first
+pub type EdgeKey = [usize; 2];
+
+/// line segment where a triangle crosses a slicing plane
+///
+/// Every endpoint lies on a mesh edge and remembers which one, so
+/// neighbouring segments can be joined by topology rather than by
+/// comparing coordinates. Segments run with the part on their left,
+/// assuming outward facing triangles.
+#[derive(Clone, Copy, Debug)]
+pub struct SliceSegment {
+    pub start: [f32; 2],
+    pub end: [f32; 2],
+    pub start_edge: EdgeKey,
+    pub end_edge: EdgeKey,
+    pub face: usize
+}
+
+impl SliceSegment {
+    pub fn length(&self) -> f32 {
+        let dx = self.end[0] - self.start[0];
+        let dy = self.end[1] - self.start[1];
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+/// slice a mesh at every plane in ``zs`` in a single sweep
+///
+/// Triangles are sorted by their lowest vertex once. As the plane
+/// rises, triangles whose lowest vertex falls below it join the
+/// active set and triangles lying entirely below it leave, so each
+/// triangle is only looked at for the layers it actually spans.
+///
+/// A vertex lying exactly on a plane is treated as above it. With that
+/// rule every crossing triangle has exactly one edge climbing through
+/// the plane and one dropping back, so a watertight mesh always gives
+/// closed chains, and the intersection point on a shared edge is
+/// computed the same way from both sides.
+///
+/// The result has one entry per plane in the same order as ``zs``.
+pub fn plane_sweep(mesh: &STLMesh, zs: &[f32]) -> Vec<Vec<SliceSegment>> {
+    let faces = mesh.faces();
+    let vertices = mesh.vertices();
+
+    let z_range = |face: usize| {
+        let v = faces[face].vertices;
+        let z = [vertices[v[0]][2], vertices[v[1]][2], vertices[v[2]][2]];
+        (z[0].min(z[1]).min(z[2]), z[0].max(z[1]).max(z[2]))
+    };
+
+    let mut by_z_min: Vec<usize> = (0..faces.len()).collect();
+    by_z_min.sort_by(|&a, &b| z_range(a).0.total_cmp(&z_range(b).0));
+
+    // planes are processed bottom up but reported in the given order
+    let mut planes: Vec<usize> = (0..zs.len()).collect();
+    planes.sort_by(|&a, &b| zs[a].total_cmp(&zs[b]));
+
+    let mut layers = vec![vec![]; zs.len()];
+    let mut active: Vec<usize> = vec![];
+    let mut next = 0;
+    for plane in planes {
+        let z = zs[plane];
+        while next < by_z_min.len() && z_range(by_z_min[next]).0 < z {
+            active.push(by_z_min[next]);
+            next += 1;
+        }
+        active.retain(|&face| z_range(face).1 >= z);
+
+        layers[plane] = active
+            .iter()
+            .filter_map(|&face| face_segment(mesh, face, z))
+            .collect();
+    }
+    layers
+}
+
+/// segment for one face known to span the plane, ``None`` when the
+/// face only touches the plane from above
+fn face_segment(mesh: &STLMesh, face: usize, z: f32) -> Option<SliceSegment> {
+    let vertices = mesh.vertices();
+    let v = mesh.faces()[face].vertices;
+    let above = [
+        vertices[v[0]][2] >= z,
+        vertices[v[1]][2] >= z,
+        vertices[v[2]][2] >= z
+    ];
+
+    // walking the face in its winding order, one edge climbs through
+    // the plane and one edge drops back through it
+    let mut entering = None;
+    let mut leaving = None;
+    for i in 0..3 {
+        let j = (i + 1) % 3;
+        match (above[i], above[j]) {
+            (false, true) => entering = Some((v[i], v[j])),
+            (true, false) => leaving = Some((v[j], v[i])),
+            _ => {}
+        }
+    }
+    let (enter_lo, enter_hi) = entering?;
+    let (leave_lo, leave_hi) = leaving?;
+
+    // for an outward facing triangle the part lies to the left
+    // when going from the dropping edge to the climbing edge
+    Some(SliceSegment {
+        start: edge_point(mesh, leave_lo, leave_hi, z),
+        end: edge_point(mesh, enter_lo, enter_hi, z),
+        start_edge: edge_key(leave_lo, leave_hi),
+        end_edge: edge_key(enter_lo, enter_hi),
+        face
+    })
+}
+
+fn edge_key(a: usize, b: usize) -> EdgeKey {
+    [a.min(b), a.max(b)]
+}
+
+/// point where the plane crosses the edge from vertex ``lo`` (below)
+/// to vertex ``hi`` (on or above), always interpolated in that
+/// direction so both faces sharing the edge get the same bits
+fn edge_point(mesh: &STLMesh, lo: usize, hi: usize, z: f32) -> [f32; 2] {
+    let a = mesh.vertices()[lo];
+    let b = mesh.vertices()[hi];
+    if b[2] == z {
+        return [b[0], b[1]];
+    }
+    let t = (z as f64 - a[2] as f64) / (b[2] as f64 - a[2] as f64);
+    [
+        (a[0] as f64 + t * (b[0] as f64 - a[0] as f64)) as f32,
+        (a[1] as f64 + t * (b[1] as f64 - a[1] as f64)) as f32
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::test_util::block;
+
+    #[test]
+    fn sweep_through_block() {
+        // bottom, middle and top, given out of order
+        let layers = block().plane_sweep(&[0., 2.5, -2.5]);
+        assert_eq!(layers.len(), 3);
+        // a vertex on the plane counts as above it, so the bottom
+        // face is not cut and the top face is
+        assert!(layers[2].is_empty());
+        for segments in &layers[..2] {
+            let perimeter: f32 = segments.iter().map(|x| x.length()).sum();
+            assert!((perimeter - 14.).abs() < 1e-4, "got {:?}", segments);
+        }
+    }
+
+    #[test]
+    fn segments_share_edges() {
+        let segments = &block().plane_sweep(&[0.])[0];
+        for seg in segments {
+            assert_eq!(segments.iter().filter(|x| x.start_edge == seg.end_edge).count(), 1);
+        }
+    }
+
+    #[test]
+    fn planes_off_the_mesh() {
+        let layers = block().plane_sweep(&[-3., 3.]);
+        assert!(layers.iter().all(|x| x.is_empty()));
+    }
+}
@@ -0,0 +1,504 @@
+/// point in the xy plane
+pub type Point = nalgebra::Point2::<f32>;
+
+/// axis aligned bounding box in the xy plane
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingBox2D {
+    pub x_min: f32,
+    pub y_min: f32,
+    pub x_max: f32,
+    pub y_max: f32
+}
+
+impl BoundingBox2D {
+    /// tightest box around a set of points, an empty set gives an
+    /// inverted box that acts as the identity for ``union``
+    pub fn from_points<'a, I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Point>
+    {
+        let mut bb = BoundingBox2D {
+            x_min: f32::MAX,
+            y_min: f32::MAX,
+            x_max: f32::MIN,
+            y_max: f32::MIN
+        };
+        for p in points {
+            bb.x_min = bb.x_min.min(p.x);
+            bb.y_min = bb.y_min.min(p.y);
+            bb.x_max = bb.x_max.max(p.x);
+            bb.y_max = bb.y_max.max(p.y);
+        }
+        bb
+    }
+
+    pub fn center(&self) -> Point {
+        Point::new(0.5 * (self.x_min + self.x_max), 0.5 * (self.y_min + self.y_max))
+    }
+
+    pub fn contains(&self, point: &Point) -> bool {
+        self.x_min <= point.x && point.x <= self.x_max &&
+        self.y_min <= point.y && point.y <= self.y_max
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x_min > self.x_max || self.y_min > self.y_max
+    }
+
+    pub fn union(&self, other: &BoundingBox2D) -> Self {
+        BoundingBox2D {
+            x_min: self.x_min.min(other.x_min),
+            y_min: self.y_min.min(other.y_min),
+            x_max: self.x_max.max(other.x_max),
+            y_max: self.y_max.max(other.y_max)
+        }
+    }
+}
+
+/// winding direction of a closed polygon
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    Clockwise,
+    CounterClockwise
+}
+
+/// open chain of points
+#[derive(Clone, Debug, Default)]
+pub struct Polyline {
+    pub points: Vec<Point>
+}
+
+impl Polyline {
+    pub fn new(points: Vec<Point>) -> Self {
+        Self { points }
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox2D {
+        BoundingBox2D::from_points(&self.points)
+    }
+
+    pub fn length(&self) -> f32 {
+        self.points
+            .windows(2)
+            .map(|x| (x[1] - x[0]).norm())
+            .sum()
+    }
+
+    pub fn reverse(&mut self) {
+        self.points.reverse();
+    }
+
+    /// Douglas–Peucker simplification, endpoints are always kept
+    pub fn simplify(&self, tolerance: f32) -> Self {
+        Self::new(douglas_peucker(&self.points, tolerance))
+    }
+}
+
+/// closed polygon, the first point is not repeated at the end
+///
+/// Outer contours run counter clockwise and holes clockwise, see
+/// ``ExPolygon``. Nothing enforces that on a bare polygon.
+#[derive(Clone, Debug, Default)]
+pub struct Polygon {
+    pub points: Vec<Point>
+}
+
+impl Polygon {
+    pub fn new(points: Vec<Point>) -> Self {
+        Self { points }
+    }
+
+    /// regular polygon with its corners on the circle, counter clockwise
+    pub fn circle(center: Point, radius: f32, segments: usize) -> Self {
+        Self::new(
+            (0..segments)
+                .map(|k| {
+                    let (s, c) = (2. * std::f32::consts::PI * k as f32 / segments as f32).sin_cos();
+                    Point::new(center.x + radius * c, center.y + radius * s)
+                })
+                .collect()
+        )
+    }
+
+    /// absolute area
+    pub fn area(&self) -> f32 {
+        self.signed_area().abs()
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox2D {
+        BoundingBox2D::from_points(&self.points)
+    }
+
+    /// closest point on the outline to ``point``, with the index of
+    /// the point starting the edge it lies on, none when there is no
+    /// outline
+    pub fn closest_point(&self, point: &Point) -> Option<(usize, Point)> {
+        self.edges()
+            .enumerate()
+            .map(|(i, (a, b))| {
+                let ab = b - a;
+                let len2 = ab.norm_squared();
+                let t = if len2 > 0. { ((point - a).dot(&ab) / len2).clamp(0., 1.) } else { 0. };
+                (i, a + ab * t)
+            })
+            .min_by(|a, b| (a.1 - point).norm_squared().total_cmp(&(b.1 - point).norm_squared()))
+    }
+
+    /// crossing number test, points exactly on
+    /// an edge may land on either side
+    pub fn contains(&self, point: &Point) -> bool {
+        let mut inside = false;
+        for (a, b) in self.edges() {
+            if (a.y > point.y) != (b.y > point.y) {
+                let x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+                if point.x < x {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    /// consecutive point pairs including the closing edge
+    pub fn edges(&self) -> impl Iterator<Item = (&Point, &Point)> {
+        let n = self.points.len();
+        (0..n).map(move |i| (&self.points[i], &self.points[(i + 1) % n]))
+    }
+
+    pub fn is_counter_clockwise(&self) -> bool {
+        self.signed_area() > 0.
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn make_clockwise(&mut self) {
+        if self.is_counter_clockwise() {
+            self.reverse();
+        }
+    }
+
+    pub fn make_counter_clockwise(&mut self) {
+        if !self.is_counter_clockwise() {
+            self.reverse();
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        if self.is_counter_clockwise() {
+            Orientation::CounterClockwise
+        } else {
+            Orientation::Clockwise
+        }
+    }
+
+    pub fn perimeter(&self) -> f32 {
+        self.edges().map(|(a, b)| (b - a).norm()).sum()
+    }
+
+    pub fn reverse(&mut self) {
+        self.points.reverse();
+    }
+
+    /// shoelace formula, positive when counter clockwise
+    pub fn signed_area(&self) -> f32 {
+        // accumulate in f64 relative to the first point, large
+        // coordinates otherwise swamp the area of thin loops
+        let Some(origin) = self.points.first() else {
+            return 0.;
+        };
+        let mut area = 0.0_f64;
+        for (a, b) in self.edges() {
+            let (ax, ay) = ((a.x - origin.x) as f64, (a.y - origin.y) as f64);
+            let (bx, by) = ((b.x - origin.x) as f64, (b.y - origin.y) as f64);
+            area += ax * by - bx * ay;
+        }
+        (0.5 * area) as f32
+    }
+
+    /// Douglas–Peucker simplification of the closed loop
+    ///
+    /// The loop is split at two points far apart from each other, both
+    /// of which are corners that any simplification has to keep, and
+    /// each half is simplified separately. That way the result does not
+    /// depend on where the loop happens to start.
+    pub fn simplify(&self, tolerance: f32) -> Self {
+        if self.points.len() <= 3 {
+            return self.clone();
+        }
+        let start = farthest(&self.points, &self.points[0]);
+        let rotated = self.split_at(start).points;
+        // the last point closes the loop and repeats the first
+        let far = farthest(&rotated[..rotated.len() - 1], &rotated[0]);
+
+        let mut points = douglas_peucker(&rotated[..=far], tolerance);
+        points.pop();
+        points.extend(douglas_peucker(&rotated[far..], tolerance));
+        points.pop();
+
+        if points.len() < 3 {
+            return self.clone();
+        }
+        Self::new(points)
+    }
+
+    /// open the loop at point ``n``, closing it back on itself
+    pub fn split_at(&self, n: usize) -> Polyline {
+        let mut points = self.points[n..].to_vec();
+        points.extend_from_slice(&self.points[..=n]);
+        Polyline::new(points)
+    }
+
+    pub fn translate(&mut self, x: f32, y: f32) {
+        for p in self.points.iter_mut() {
+            p.x += x;
+            p.y += y;
+        }
+    }
+}
+
+/// region of a layer, an outer contour with zero or more holes
+///
+/// The contour is kept counter clockwise and the holes clockwise, so
+/// the filled region is always on the left of every edge.
+#[derive(Clone, Debug, Default)]
+pub struct ExPolygon {
+    pub contour: Polygon,
+    pub holes: Vec<Polygon>
+}
+
+impl ExPolygon {
+    pub fn new(contour: Polygon, holes: Vec<Polygon>) -> Self {
+        let mut expolygon = Self { contour, holes };
+        expolygon.fix_winding();
+        expolygon
+    }
+
+    /// sort loops into contours and holes by how deeply they nest
+    ///
+    /// Loops inside an even number of other loops are contours, the
+    /// rest are holes of the smallest contour around them. Islands
+    /// sitting inside holes come out as their own ``ExPolygon``.
+    pub fn from_polygons(polygons: Vec<Polygon>) -> Vec<ExPolygon> {
+        let mut polygons: Vec<Polygon> = polygons
+            .into_iter()
+            .filter(|x| x.len() >= 3 && x.area() > 0.)
+            .collect();
+        // parents always have a larger area than their children
+        polygons.sort_by(|a, b| b.area().total_cmp(&a.area()));
+
+        let mut parent: Vec<Option<usize>> = vec![None; polygons.len()];
+        let mut depth = vec![0; polygons.len()];
+        for i in 0..polygons.len() {
+            let probe = polygons[i].points[0];
+            let bb = polygons[i].bounding_box();
+            // the last containing loop in area order is the tightest one
+            for j in (0..i).rev() {
+                let outer_bb = polygons[j].bounding_box();
+                if outer_bb.contains(&Point::new(bb.x_min, bb.y_min)) &&
+                   outer_bb.contains(&Point::new(bb.x_max, bb.y_max)) &&
+                   polygons[j].contains(&probe) {
+                    parent[i] = Some(j);
+                    depth[i] = depth[j] + 1;
+                    break;
+                }
+            }
+        }
+
+        let mut expolygons = vec![];
+        let mut index = vec![None; polygons.len()];
+        for i in 0..polygons.len() {
+            if depth[i] % 2 == 0 {
+                index[i] = Some(expolygons.len());
+                expolygons.push(ExPolygon::default());
+            }
+        }
+        for (i, polygon) in polygons.into_iter().enumerate() {
+            match (depth[i] % 2, parent[i]) {
+                (0, _) => expolygons[index[i].unwrap()].contour = polygon,
+                (_, Some(p)) => expolygons[index[p].unwrap()].holes.push(polygon),
+                _ => unreachable!("holes always have a parent")
+            }
+        }
+        for expolygon in expolygons.iter_mut() {
+            expolygon.fix_winding();
+        }
+        expolygons
+    }
+
+    /// filled area, contour minus holes
+    pub fn area(&self) -> f32 {
+        self.contour.area() - self.holes.iter().map(|x| x.area()).sum::<f32>()
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox2D {
+        self.contour.bounding_box()
+    }
+
+    pub fn contains(&self, point: &Point) -> bool {
+        self.contour.contains(point) && !self.holes.iter().any(|x| x.contains(point))
+    }
+
+    /// make the contour counter clockwise and the holes clockwise
+    pub fn fix_winding(&mut self) {
+        self.contour.make_counter_clockwise();
+        for hole in self.holes.iter_mut() {
+            hole.make_clockwise();
+        }
+    }
+
+    /// contour followed by holes
+    pub fn polygons(&self) -> impl Iterator<Item = &Polygon> {
+        std::iter::once(&self.contour).chain(self.holes.iter())
+    }
+
+    /// total length of the contour and every hole
+    pub fn perimeter(&self) -> f32 {
+        self.polygons().map(|x| x.perimeter()).sum()
+    }
+
+    pub fn simplify(&self, tolerance: f32) -> Self {
+        Self::new(
+            self.contour.simplify(tolerance),
+            self.holes.iter().map(|x| x.simplify(tolerance)).collect()
+        )
+    }
+
+    pub fn translate(&mut self, x: f32, y: f32) {
+        self.contour.translate(x, y);
+        for hole in self.holes.iter_mut() {
+            hole.translate(x, y);
+        }
+    }
+}
+
+/// distance from ``p`` to the segment ``a`` ``b``
+pub fn point_segment_distance(p: &Point, a: &Point, b: &Point) -> f32 {
+    let ab = b - a;
+    let len2 = ab.norm_squared();
+    if len2 == 0. {
+        return (p - a).norm();
+    }
+    let t = ((p - a).dot(&ab) / len2).clamp(0., 1.);
+    (p - (a + ab * t)).norm()
+}
+
+/// index of the point farthest from ``p``
+fn farthest(points: &[Point], p: &Point) -> usize {
+    (0..points.len())
+        .max_by(|&a, &b| {
+            (points[a] - p).norm_squared()
+                .total_cmp(&(points[b] - p).norm_squared())
+        })
+        .unwrap()
+}
+
+fn douglas_peucker(points: &[Point], tolerance: f32) -> Vec<Point> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    // explicit stack, recursion depth can get large on dense scans
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = stack.pop() {
+        let mut max_dist = 0.;
+        let mut index = start;
+        for i in start + 1..end {
+            let dist = point_segment_distance(&points[i], &points[start], &points[end]);
+            if dist > max_dist {
+                max_dist = dist;
+                index = i;
+            }
+        }
+        if max_dist > tolerance {
+            keep[index] = true;
+            stack.push((start, index));
+            stack.push((index, end));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, k)| *k)
+        .map(|(p, _)| *p)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::test_util::square;
+
+    #[test]
+    fn area_and_containment() {
+        let mut polygon = square(0., 0., 10.);
+        assert!((polygon.signed_area() - 100.).abs() < 1e-4);
+        assert!((polygon.perimeter() - 40.).abs() < 1e-4);
+        assert!(polygon.contains(&Point::new(5., 5.)));
+        assert!(!polygon.contains(&Point::new(11., 5.)));
+        polygon.reverse();
+        assert!((polygon.signed_area() + 100.).abs() < 1e-4);
+        assert_eq!(polygon.orientation(), Orientation::Clockwise);
+    }
+
+    #[test]
+    fn closest_point_on_outline() {
+        let (edge, point) = square(0., 0., 10.).closest_point(&Point::new(5., -1.)).unwrap();
+        assert_eq!(edge, 0);
+        assert!((point - Point::new(5., 0.)).norm() < 1e-6);
+        assert!(Polygon::default().closest_point(&Point::origin()).is_none());
+    }
+
+    #[test]
+    fn new_fixes_winding() {
+        let mut contour = square(0., 0., 10.);
+        contour.make_clockwise();
+        let expolygon = ExPolygon::new(contour, vec![square(3., 3., 4.)]);
+        assert!(expolygon.contour.is_counter_clockwise());
+        assert!(!expolygon.holes[0].is_counter_clockwise());
+        assert!((expolygon.area() - 84.).abs() < 1e-4);
+        assert!(expolygon.contains(&Point::new(1., 1.)));
+        assert!(!expolygon.contains(&Point::new(5., 5.)));
+    }
+
+    #[test]
+    fn island_inside_hole() {
+        // outer contour, a hole in it and an island in the hole, all
+        // given counter clockwise and in no particular order
+        let polygons = vec![square(4., 4., 2.), square(0., 0., 10.), square(2., 2., 6.)];
+        let mut expolygons = ExPolygon::from_polygons(polygons);
+        expolygons.sort_by(|a, b| b.area().total_cmp(&a.area()));
+        assert_eq!(expolygons.len(), 2);
+        assert_eq!(expolygons[0].holes.len(), 1);
+        assert!((expolygons[0].area() - 64.).abs() < 1e-4);
+        assert!(!expolygons[0].holes[0].is_counter_clockwise());
+        assert!(expolygons[1].holes.is_empty());
+        assert!((expolygons[1].area() - 4.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn simplify_from_the_middle_of_a_side() {
+        // starts half way along the bottom, with more points in line
+        let polygon = Polygon::new(
+            [(5., 0.), (10., 0.), (10., 5.), (10., 10.), (5., 10.), (0., 10.), (0., 5.), (0., 0.)]
+                .iter()
+                .map(|&(x, y)| Point::new(x, y))
+                .collect()
+        );
+        let simplified = polygon.simplify(0.01);
+        assert_eq!(simplified.len(), 4);
+        assert!((simplified.signed_area() - 100.).abs() < 1e-4);
+        for corner in [(0., 0.), (10., 0.), (10., 10.), (0., 10.)] {
+            assert!(simplified.points.contains(&Point::new(corner.0, corner.1)));
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use super::{ExPolygon, Point, Polygon, STLMesh};
+
+/// the 3 by 4 by 5 mm block from the examples, centered on the origin
+pub fn block() -> STLMesh {
+    STLMesh::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test/example/Block.stl").to_string())
+}
+
+/// 10 mm square with a 4 mm square hole in the middle
+pub fn ring() -> ExPolygon {
+    let mut hole = square(3., 3., 4.);
+    hole.make_clockwise();
+    ExPolygon::new(square(0., 0., 10.), vec![hole])
+}
+
+/// counter clockwise square with its lower left corner at x, y
+pub fn square(x: f32, y: f32, size: f32) -> Polygon {
+    Polygon::new(vec![
+        Point::new(x, y),
+        Point::new(x + size, y),
+        Point::new(x + size, y + size),
+        Point::new(x, y + size)
+    ])
+}
@@ -4,9 +4,10 @@ use arcwelderlib_sys;
 use clap::{Parser, Subcommand};
 use slicey::{
     geometry::STLMesh,
-    settings::Settings,
+    settings::{MaterialLibrary, PrinterSettings, Settings},
     slicer::{
         DLPSlicer,
+        placement,
         FFFSlicer,
         Slicer
     }
@@ -43,9 +44,25 @@ enum Commands {
         /// Path to settings file
         #[arg(long)]
         settings_file: String,
+        /// Path to a printer file, replaces the printer section of the settings
+        #[arg(long)]
+        printer_file: Option<String>,
+        /// Path to a material library to look the material name up in,
+        /// the built in one is used otherwise
+        #[arg(long)]
+        material_library: Option<String>,
         /// Whether or not to use arcwelder. Requires cargo build --features arcwelder
         #[arg(long)]
-        arcwelder: bool
+        arcwelder: bool,
+        /// Scale factor, one for every axis or x,y,z
+        #[arg(long, value_delimiter = ',')]
+        scale: Option<Vec<f32>>,
+        /// Degrees to rotate the parts counter clockwise about z
+        #[arg(long)]
+        rotate_z: Option<f32>,
+        /// x,y to center the parts on instead of the bed center
+        #[arg(long, value_delimiter = ',')]
+        position: Option<Vec<f32>>
     }
 }
 
@@ -60,18 +77,43 @@ fn main() {
                 let slicer = DLPSlicer::new(settings, stl_mesh);
                 let _ = slicer.slice(&image_folder);
             },
-            Commands::FFF { gcode_file, stl_files, settings_file, arcwelder } => {
+            Commands::FFF {
+                gcode_file, stl_files, settings_file, printer_file, material_library,
+                arcwelder, scale, rotate_z, position
+            } => {
 
                 println!("STL file      = {:?}", stl_files);
-                let settings = Settings::new(&settings_file);
+                let mut settings = Settings::new(&settings_file);
+                if let Some(printer_file) = printer_file {
+                    settings.printer = Some(PrinterSettings::new(&printer_file));
+                }
+                let library = match material_library {
+                    Some(x) => MaterialLibrary::new(&x),
+                    None => MaterialLibrary::builtin()
+                };
+                settings.material.resolve(&library);
                 let mut stl_meshes: Vec<STLMesh> = stl_files
                     .iter()
                     .map(|x| STLMesh::new(x.clone()))
                     .collect();
-                
-                let z_offset = -1.0 * stl_meshes[0].bounding_box().z_min;
-                let _ = stl_meshes[0].translate(20.0,20.0,z_offset);
-                let _ = stl_meshes[0].scale(10.0, 10.0, 10.0);
+
+                // the command line wins over the settings file
+                let placement = settings.placement.as_ref();
+                let scale = match scale.as_deref() {
+                    Some(&[x]) => [x, x, x],
+                    Some(&[x, y, z]) => [x, y, z],
+                    Some(x) => panic!("Scale takes one or three factors, got {:?}", x),
+                    None => placement.map_or([1., 1., 1.], |x| x.scale)
+                };
+                let rotation = rotate_z.unwrap_or(placement.map_or(0., |x| x.rotation));
+                let center = match position.as_deref() {
+                    Some(&[x, y]) => Some([x, y]),
+                    Some(x) => panic!("Position takes x,y, got {:?}", x),
+                    None => placement
+                        .and_then(|x| x.position)
+                        .or(settings.printer.as_ref().map(|x| x.bed_center()))
+                };
+                placement::place(&mut stl_meshes, scale, rotation, center);
 
                 let slicer = FFFSlicer::new(settings, stl_meshes);
                 let _ = slicer.slice(&gcode_file);
@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 // use std::fmt::{Debug, Display};
 use std::fmt;
 use std::fs::File;
@@ -19,35 +20,533 @@ pub enum FloatOrVecOfFloats {
     VecOfFLoats(Vec<f32>)
 }
 
+/// text given whole or as a list of lines
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StringOrVecOfStrings {
+    String(String),
+    VecOfStrings(Vec<String>)
+}
+
+/// material given by name, to look up in a library, or in full
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MaterialOrName {
+    Name(String),
+    Material(MaterialSettings)
+}
+
+impl MaterialOrName {
+    /// the full material, None until a name is looked up
+    pub fn material(&self) -> Option<&MaterialSettings> {
+        match self {
+            MaterialOrName::Name(_) => None,
+            MaterialOrName::Material(x) => Some(x)
+        }
+    }
+
+    /// replace a name with the material of that name in ``library``
+    pub fn resolve(&mut self, library: &MaterialLibrary) {
+        if let MaterialOrName::Name(name) = self {
+            let mut material = library.get(name);
+            material.name = name.clone();
+            *self = MaterialOrName::Material(material);
+        }
+    }
+}
+
+impl StringOrVecOfStrings {
+    pub fn text(&self) -> String {
+        match self {
+            StringOrVecOfStrings::String(x) => x.clone(),
+            StringOrVecOfStrings::VecOfStrings(x) => x.join("\n")
+        }
+    }
+}
+
+impl FloatOrVecOfFloats {
+    /// a list without any values, which holds for no layer
+    pub fn is_empty(&self) -> bool {
+        matches!(self, FloatOrVecOfFloats::VecOfFLoats(x) if x.is_empty())
+    }
+
+    /// value for a layer above the first, see ``IntOrVecOfInts::for_layer``
+    pub fn for_layer(&self, layer: usize) -> f32 {
+        match self {
+            FloatOrVecOfFloats::Float(x) => *x,
+            FloatOrVecOfFloats::VecOfFLoats(x) => *list_for_layer(x, layer)
+        }
+    }
+}
+
+impl IntOrVecOfInts {
+    /// a list without any values, which holds for no layer
+    pub fn is_empty(&self) -> bool {
+        matches!(self, IntOrVecOfInts::VecOfInts(x) if x.is_empty())
+    }
+
+    /// value for a layer above the first
+    ///
+    /// The ``layer_n_*`` settings start at layer 1, layer 0 has its own
+    /// ``layer_0_*`` setting. A single value holds for every layer, a
+    /// list gives one value per layer starting at layer 1, and its last
+    /// value holds for every layer after that.
+    pub fn for_layer(&self, layer: usize) -> i32 {
+        match self {
+            IntOrVecOfInts::Int(x) => *x,
+            IntOrVecOfInts::VecOfInts(x) => *list_for_layer(x, layer)
+        }
+    }
+}
+
+fn list_for_layer<T>(list: &[T], layer: usize) -> &T {
+    let index = layer.saturating_sub(1).min(list.len().saturating_sub(1));
+    list.get(index).expect("Got an empty list for a layer n setting")
+}
+
+/// layer heights following the slope of the surface, thin where it
+/// is close to flat and thick on steep walls
+#[derive(Clone, Debug, Deserialize)]
+pub struct AdaptiveLayerHeightSettings {
+    pub max_height: f32,
+    pub min_height: f32,
+    /// 0 prints flat surfaces at the max height, 1 at the min height
+    pub quality: f32
+}
+
+/// one limit per axis
+#[derive(Clone, Debug, Deserialize)]
+pub struct AxisLimits {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub e: f32
+}
+
+/// lines around the first layer, touching the part
 #[derive(Clone, Debug, Deserialize)]
 pub struct BrimSettings {
+    /// only put brim around corners sharper than ``ear_max_angle``
+    #[serde(default)]
+    pub ears: bool,
+    /// corner angle in degrees below which a corner gets an ear
+    #[serde(default = "default_ear_max_angle")]
+    pub ear_max_angle: f32,
+    /// also put brim inside holes
+    #[serde(default)]
+    pub inner_holes: bool,
+    /// how far the brim reaches from the part, also the radius of ears
+    pub width: f32
+}
+
+fn default_ear_max_angle() -> f32 {
+    125.
+}
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExtrusionSettings {
+    /// nominal diameter, a measured one from the material takes over
+    pub filament_diameter: f32,
+    pub line_width: f32,
+    /// emit E as per move increments (M83) instead of a running total (M82)
+    #[serde(default)]
+    pub relative_e: bool,
+    /// emit E in cubic millimeters of plastic and let the firmware
+    /// convert using the filament diameter (M200)
+    #[serde(default)]
+    pub volumetric_e: bool
 }
 
+/// part cooling fan and layer time, speeds are fractions of full
+/// speed, times in seconds and feed rates in mm/min
+#[derive(Clone, Debug, Deserialize)]
+pub struct FanSettings {
+    /// speed while printing bridges, the layer speed when left out
+    #[serde(default)]
+    pub bridge_speed: Option<f32>,
+    /// layers at the bottom printed with the fan off
+    #[serde(default)]
+    pub disable_layer_count: u32,
+    pub max_speed: f32,
+    /// floor for feed rates slowed down for the min layer time
+    #[serde(default = "default_min_feed_rate")]
+    pub min_feed_rate: f32,
+    /// layers quicker than this are slowed down
+    #[serde(default)]
+    pub min_layer_time: f32,
+    pub min_speed: f32,
+    /// layers after the disabled ones over which the fan speeds up
+    #[serde(default)]
+    pub ramp_layer_count: u32,
+    /// layers quicker than this get more fan, up to the max speed at
+    /// the min layer time
+    #[serde(default)]
+    pub raise_below_layer_time: f32
+}
+
+fn default_min_feed_rate() -> f32 {
+    600.
+}
+
+/// g-code written around the print, see ``gcode::template::render``
+/// for the placeholders and conditionals, left out ones fall back to
+/// the defaults in ``gcode``
+#[derive(Clone, Debug, Deserialize)]
+pub struct GcodeSettings {
+    /// after the last layer
+    #[serde(default)]
+    pub end: Option<StringOrVecOfStrings>,
+    /// after the move up to every layer
+    #[serde(default)]
+    pub layer_change: Option<StringOrVecOfStrings>,
+    /// after the header, before the first layer
+    #[serde(default)]
+    pub start: Option<StringOrVecOfStrings>
+}
+
+/// sparse infill, feed rates are in mm/min
 #[derive(Clone, Debug, Deserialize)]
 pub struct InfillSettings {
+    /// fraction of the infill area covered by lines, 0 to 1
+    pub density: f32,
+    pub feed_rate: f32,
+    /// direction of the first line family, degrees from the x axis
+    #[serde(default)]
+    pub line_angle: f32,
+    /// degrees added to the line angle on every layer
+    #[serde(default)]
+    pub layer_rotation: f32,
+    /// how far infill reaches into the innermost wall, as a fraction of the line width
+    #[serde(default)]
+    pub overlap: f32,
+    pub pattern: InfillPattern
+}
 
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InfillPattern {
+    /// three families of planes tilted so they form stacked cubes
+    /// standing on a corner, each layer shifts the lines a little
+    Cubic,
+    /// Schwarz diamond surface
+    Diamond,
+    /// lines crossing at right angles
+    Grid,
+    /// gyroid surface, close to isotropic
+    Gyroid,
+    /// parallel lines
+    Lines,
+    /// Schwarz primitive surface
+    #[serde(rename = "schwarz_p")]
+    SchwarzP,
+    /// lines in three directions 60 degrees apart
+    Triangles
 }
 
 // need to check that at least one of 
 // layer_n_height or layer_n_heights is Some
 #[derive(Clone, Debug, Deserialize)]
 pub struct LayerHeightSettings {
+    /// replaces layer_n_height above the first layer when set
+    #[serde(default)]
+    pub adaptive: Option<AdaptiveLayerHeightSettings>,
     pub layer_0_height: f32,
     pub layer_n_height: FloatOrVecOfFloats
 }
 
+/// filament for FFF, temperatures are in C
+#[derive(Clone, Debug, Deserialize)]
+pub struct MaterialSettings {
+    pub bed_temperature: f32,
+    /// in g/cm^3, for the weight of filament used
+    pub density: f32,
+    #[serde(default)]
+    pub fan: Option<FanSettings>,
+    /// measured diameter, replaces the nominal one of the extrusion settings
+    #[serde(default)]
+    pub filament_diameter: Option<f32>,
+    pub first_layer_bed_temperature: f32,
+    pub first_layer_temperature: f32,
+    /// scales the plastic laid down in every extruding move
+    #[serde(default = "default_flow")]
+    pub flow: f32,
+    /// filled in from the library key when looked up by name
+    #[serde(default)]
+    pub name: String,
+    pub temperature: f32
+}
+
+fn default_flow() -> f32 {
+    1.
+}
+
+/// materials by name, read from a json object of name to material
+#[derive(Clone, Debug, Deserialize)]
+pub struct MaterialLibrary(HashMap<String, MaterialSettings>);
+
+impl MaterialLibrary {
+    pub fn new(file_name: &str) -> Self {
+        let file = File::open(file_name)
+            .unwrap_or_else(|_| panic!("Failed to open material library {:?}", file_name));
+        let reader = BufReader::new(file);
+        serde_json::from_reader(reader).unwrap()
+    }
+
+    /// common filaments that ship with the slicer
+    pub fn builtin() -> Self {
+        serde_json::from_str(include_str!("materials.json")).unwrap()
+    }
+
+    pub fn get(&self, name: &str) -> MaterialSettings {
+        self.0
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| {
+                let mut names: Vec<&String> = self.0.keys().collect();
+                names.sort();
+                panic!("Material {:?} is not in the material library, which has {:?}", name, names)
+            })
+    }
+}
+
+/// feed rates are in mm/min, as written to F
 #[derive(Clone, Debug, Deserialize)]
 pub struct PerimeterSettings {
     pub layer_0_feed_rate: f32,
     pub layer_n_feed_rate: FloatOrVecOfFloats,
     pub layer_0_wall_line_count: u32,
-    pub layer_n_wall_line_count: IntOrVecOfInts
+    pub layer_n_wall_line_count: IntOrVecOfInts,
+    /// where wall loops start and end
+    #[serde(default)]
+    pub seam: SeamPosition,
+    /// start every loop as close as it gets to this xy instead
+    #[serde(default)]
+    pub seam_point: Option<[f32; 2]>
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SeamPosition {
+    /// close to the seams of the layer below, so they line up
+    #[default]
+    Aligned,
+    /// close to where the nozzle is, for the shortest travel
+    Nearest,
+    /// anywhere, spreading the seams over the surface
+    Random,
+    /// at the back of the loop, furthest along y
+    Rear,
+    /// in the sharpest corner, concave ones first where it hides best
+    Sharpest
+}
+
+/// where the parts go on the bed, applied to every mesh before slicing
+#[derive(Clone, Debug, Deserialize)]
+pub struct PlacementSettings {
+    /// xy to center the parts on instead of the bed center
+    #[serde(default)]
+    pub position: Option<[f32; 2]>,
+    /// degrees counter clockwise about z
+    #[serde(default)]
+    pub rotation: f32,
+    /// per axis scale factors
+    #[serde(default = "default_scale")]
+    pub scale: [f32; 3]
+}
+
+fn default_scale() -> [f32; 3] {
+    [1., 1., 1.]
+}
+
+/// the machine, lengths in mm, feed rates in mm/min and
+/// accelerations in mm/s^2
+#[derive(Clone, Debug, Deserialize)]
+pub struct PrinterSettings {
+    pub bed_shape: BedShape,
+    /// x and y extent of the bed, both are the diameter for a circular bed
+    pub bed_size: [f32; 2],
+    #[serde(default)]
+    pub max_accelerations: Option<AxisLimits>,
+    #[serde(default)]
+    pub max_feed_rates: Option<AxisLimits>,
+    /// highest z the nozzle can print at
+    pub max_height: f32,
+    pub nozzle_diameter: f32,
+    /// where x = y = 0 is on the bed
+    #[serde(default)]
+    pub origin: BedOrigin
 }
 
+impl PrinterSettings {
+    /// read a printer on its own, laid out like the printer section
+    pub fn new(file_name: &str) -> Self {
+        let file = File::open(file_name)
+            .unwrap_or_else(|_| panic!("Failed to open printer file {:?}", file_name));
+        let reader = BufReader::new(file);
+        serde_json::from_reader(reader).unwrap()
+    }
+
+    /// xy of the middle of the bed
+    pub fn bed_center(&self) -> [f32; 2] {
+        match self.origin {
+            BedOrigin::Center => [0., 0.],
+            BedOrigin::Corner => [0.5 * self.bed_size[0], 0.5 * self.bed_size[1]]
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BedShape {
+    Circular,
+    Rectangular
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BedOrigin {
+    /// in the middle of the bed, usual on delta printers
+    Center,
+    /// at the front left corner of the bed
+    #[default]
+    Corner
+}
+
+/// layers printed under the part, feed rates are in mm/min
+///
+/// The base is printed with wide spaced lines, the interface denser
+/// across it and the surface solid, the part then starts ``air_gap``
+/// above the top of the raft.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RaftSettings {
+    #[serde(default)]
+    pub air_gap: f32,
+    pub base_layer_count: u32,
+    pub base_layer_height: f32,
+    pub base_line_spacing: f32,
+    pub feed_rate: f32,
+    pub interface_layer_count: u32,
+    pub interface_layer_height: f32,
+    pub interface_line_spacing: f32,
+    /// how far the raft reaches past the first layer
+    pub margin: f32,
+    pub surface_layer_count: u32,
+    pub surface_layer_height: f32
+}
+
+impl RaftSettings {
+    /// how far up the part starts, every raft layer and the air gap
+    pub fn height(&self) -> f32 {
+        self.base_layer_count as f32 * self.base_layer_height +
+            self.interface_layer_count as f32 * self.interface_layer_height +
+            self.surface_layer_count as f32 * self.surface_layer_height +
+            self.air_gap
+    }
+}
+
+/// solid top and bottom layers, feed rates are in mm/min
+#[derive(Clone, Debug, Deserialize)]
+pub struct SkinSettings {
+    pub bottom_layer_count: u32,
+    pub feed_rate: f32,
+    /// direction of the skin lines on the first layer, degrees from
+    /// the x axis, every following layer turns them a quarter
+    #[serde(default)]
+    pub line_angle: f32,
+    /// skin is made at least this thick, adding layers to the counts
+    /// where the layers are too thin to reach it
+    #[serde(default)]
+    pub min_thickness: f32,
+    pub top_layer_count: u32
+}
+
+/// loops around the first layer, clear of the part
 #[derive(Clone, Debug, Deserialize)]
 pub struct SkirtSettings {
+    /// gap between the part, or its brim, and the skirt
+    pub distance: f32,
+    pub line_count: u32,
+    /// filament in mm the skirt uses at least, adding loops if needed
+    #[serde(default)]
+    pub min_length: f32
+}
+
+/// support under overhangs, feed rates are in mm/min
+#[derive(Clone, Debug, Deserialize)]
+pub struct SupportSettings {
+    /// overhangs further than this from vertical, in degrees, are supported
+    pub angle: f32,
+    /// how far from vertical tree branches may lean, in degrees
+    #[serde(default = "default_branch_angle")]
+    pub branch_angle: f32,
+    /// spacing of the tree contact points over an overhang
+    #[serde(default = "default_branch_distance")]
+    pub branch_distance: f32,
+    /// radius of the tree branch tips, merged branches get thicker
+    #[serde(default = "default_branch_radius")]
+    pub branch_radius: f32,
+    /// only support what can be reached from the bed, never rest on the part
+    #[serde(default)]
+    pub buildplate_only: bool,
+    pub density: f32,
+    pub feed_rate: f32,
+    /// solid layers at the top of the support, under the part
+    #[serde(default)]
+    pub interface_layer_count: u32,
+    #[serde(default)]
+    pub line_angle: f32,
+    pub pattern: InfillPattern,
+    #[serde(default)]
+    pub strategy: SupportStrategy,
+    /// gap kept between the support and the sides of the part
+    pub xy_distance: f32,
+    /// gap between the support and the part above or below it,
+    /// taken up by whole layers
+    pub z_distance: f32
+}
+
+fn default_branch_angle() -> f32 {
+    40.
+}
+
+fn default_branch_distance() -> f32 {
+    3.
+}
+
+fn default_branch_radius() -> f32 {
+    1.
+}
 
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SupportStrategy {
+    /// columns straight down, filled with the support pattern
+    #[default]
+    Grid,
+    /// branches that merge into trunks on the way down
+    Tree
+}
+
+/// non extruding moves, feed rates are in mm/min
+#[derive(Clone, Debug, Deserialize)]
+pub struct TravelSettings {
+    pub feed_rate: f32,
+    /// filament pulled back before a travel, 0 disables retraction
+    pub retraction_distance: f32,
+    pub retraction_feed_rate: f32,
+    /// filament pushed on top of the retraction distance when priming
+    #[serde(default)]
+    pub retraction_extra_prime: f32,
+    /// shorter travels do not retract
+    #[serde(default)]
+    pub retraction_min_travel: f32,
+    /// let the firmware retract with G10/G11, the distances
+    /// and speeds then come from the firmware settings
+    #[serde(default)]
+    pub firmware_retraction: bool,
+    /// lift the nozzle by this much during retracted travels
+    #[serde(default)]
+    pub z_hop: f32
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -61,12 +560,20 @@ pub struct XYResolution {
 #[derive(Clone, Debug, Deserialize)]
 pub struct Settings {
     pub brim: Option<BrimSettings>,
+    pub extrusion: Option<ExtrusionSettings>,
+    pub gcode: Option<GcodeSettings>,
     pub infill: Option<InfillSettings>,
     pub layer_height: LayerHeightSettings,
-    pub material: String,
+    pub material: MaterialOrName,
     pub name: String,
     pub perimeter: Option<PerimeterSettings>,
+    pub placement: Option<PlacementSettings>,
+    pub printer: Option<PrinterSettings>,
+    pub raft: Option<RaftSettings>,
+    pub skin: Option<SkinSettings>,
     pub skirt: Option<SkirtSettings>,
+    pub support: Option<SupportSettings>,
+    pub travel: Option<TravelSettings>,
     pub xy_resolution: Option<XYResolution>
 }
 
@@ -76,9 +583,28 @@ impl Settings {
         let reader = BufReader::new(file);
         let json_settings: Settings = 
             serde_json::from_reader(reader).unwrap();
+        json_settings.check_layer_lists();
         json_settings
     }
 
+    /// per layer lists need a value to repeat past their end, so an
+    /// empty one is refused at load rather than when slicing reaches it
+    fn check_layer_lists(&self) {
+        let mut empty = vec![];
+        if self.layer_height.layer_n_height.is_empty() {
+            empty.push("layer_height.layer_n_height");
+        }
+        if let Some(perimeter) = &self.perimeter {
+            if perimeter.layer_n_feed_rate.is_empty() {
+                empty.push("perimeter.layer_n_feed_rate");
+            }
+            if perimeter.layer_n_wall_line_count.is_empty() {
+                empty.push("perimeter.layer_n_wall_line_count");
+            }
+        }
+        assert!(empty.is_empty(), "Settings {:?} need at least one value", empty);
+    }
+
     pub fn to_gcode_comment(&self) -> String {
         let s = format!("{}", self);
         s.lines()
@@ -90,13 +616,61 @@ impl Settings {
 
 impl fmt::Display for Settings {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let _ = write!(f, "{:?}\n", self.name);
-        let _ = write!(f, "Material = {:?}\n", self.material);
-        let _ = write!(f, "{:#?}\n", self.brim);
-        let _ = write!(f, "{:#?}\n", self.infill);
-        let _ = write!(f, "{:#?}\n", self.layer_height);
-        let _ = write!(f, "{:#?}\n", self.perimeter);
-        let _ = write!(f, "{:#?}\n", self.skirt);
-        write!(f, "{:#?}\n", self.xy_resolution)
-    }
-}
\ No newline at end of file
+        let _ = writeln!(f, "{:?}", self.name);
+        let _ = writeln!(f, "Material = {:#?}", self.material);
+        let _ = writeln!(f, "{:#?}", self.brim);
+        let _ = writeln!(f, "{:#?}", self.extrusion);
+        let _ = writeln!(f, "{:#?}", self.gcode);
+        let _ = writeln!(f, "{:#?}", self.infill);
+        let _ = writeln!(f, "{:#?}", self.layer_height);
+        let _ = writeln!(f, "{:#?}", self.perimeter);
+        let _ = writeln!(f, "{:#?}", self.placement);
+        let _ = writeln!(f, "{:#?}", self.printer);
+        let _ = writeln!(f, "{:#?}", self.raft);
+        let _ = writeln!(f, "{:#?}", self.skin);
+        let _ = writeln!(f, "{:#?}", self.skirt);
+        let _ = writeln!(f, "{:#?}", self.support);
+        let _ = writeln!(f, "{:#?}", self.travel);
+        writeln!(f, "{:#?}", self.xy_resolution)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_start_at_layer_1_and_repeat_their_last_value() {
+        let counts = IntOrVecOfInts::VecOfInts(vec![3, 2, 1]);
+        assert_eq!(counts.for_layer(1), 3);
+        assert_eq!(counts.for_layer(2), 2);
+        assert_eq!(counts.for_layer(3), 1);
+        assert_eq!(counts.for_layer(50), 1);
+        let heights = FloatOrVecOfFloats::VecOfFLoats(vec![0.3, 0.2]);
+        assert_eq!(heights.for_layer(1), 0.3);
+        assert_eq!(heights.for_layer(2), 0.2);
+        assert_eq!(heights.for_layer(50), 0.2);
+    }
+
+    #[test]
+    fn single_values_hold_for_every_layer() {
+        assert_eq!(IntOrVecOfInts::Int(2).for_layer(1), 2);
+        assert_eq!(IntOrVecOfInts::Int(2).for_layer(50), 2);
+        assert_eq!(FloatOrVecOfFloats::Float(0.2).for_layer(1), 0.2);
+        assert_eq!(FloatOrVecOfFloats::Float(0.2).for_layer(50), 0.2);
+    }
+
+    #[test]
+    #[should_panic(expected = "perimeter.layer_n_wall_line_count")]
+    fn empty_lists_are_refused() {
+        let settings: Settings = serde_json::from_str(r#"{
+            "name": "test",
+            "layer_height": {"layer_0_height": 0.2, "layer_n_height": [0.2]},
+            "material": "PLA",
+            "perimeter": {
+                "layer_0_feed_rate": 1200.0, "layer_n_feed_rate": 2400.0,
+                "layer_0_wall_line_count": 2, "layer_n_wall_line_count": []
+            }
+        }"#).unwrap();
+        settings.check_layer_lists();
+    }
+}
@@ -0,0 +1,87 @@
+use crate::geometry::{clipping, offset, ClipType, ExPolygon, JoinType, Point, Polygon, Polyline};
+use crate::settings::BrimSettings;
+use std::f32::consts::PI;
+
+/// segments in the circle used for brim ears
+const EAR_SEGMENTS: usize = 32;
+
+/// area the brim covers around the first layer ``regions``
+///
+/// Without inner holes the brim is grown from the outer contours only,
+/// so holes stay clear. In ears mode only the parts of that area close
+/// to sharp convex corners of the contours are kept, which is where
+/// parts tend to lift.
+pub fn brim_area(regions: &[ExPolygon], settings: &BrimSettings) -> Vec<ExPolygon> {
+    let base: Vec<ExPolygon> = if settings.inner_holes {
+        regions.to_vec()
+    } else {
+        regions
+            .iter()
+            .map(|x| ExPolygon::new(x.contour.clone(), vec![]))
+            .collect()
+    };
+    let grown = offset::offset_expolygons(&base, settings.width, JoinType::Round);
+    let area = clipping::boolean_ex(&grown, &base, ClipType::Difference);
+    if !settings.ears {
+        return area;
+    }
+
+    let ears: Vec<ExPolygon> = regions
+        .iter()
+        .flat_map(|x| sharp_corners(&x.contour, settings.ear_max_angle))
+        .map(|x| ExPolygon::new(Polygon::circle(x, settings.width, EAR_SEGMENTS), vec![]))
+        .collect();
+    clipping::boolean_ex(&area, &ears, ClipType::Intersection)
+}
+
+/// brim lines over ``area``, the loop closest to the part first
+///
+/// Loops follow the part outline a line width apart and are cut to
+/// the area, so ears come out as arcs around their corner.
+pub fn brim_paths(
+    regions: &[ExPolygon],
+    area: &[ExPolygon],
+    settings: &BrimSettings,
+    line_width: f32
+) -> Vec<Polyline> {
+    let count = (settings.width / line_width).floor() as usize;
+    let mut paths = vec![];
+    for k in 0..count {
+        let distance = (k as f32 + 0.5) * line_width;
+        let loops: Vec<Polyline> = offset::offset_expolygons(regions, distance, JoinType::Round)
+            .iter()
+            .flat_map(|x| x.polygons())
+            .map(|x| x.split_at(0))
+            .collect();
+        paths.extend(clipping::clip_polylines(&loops, area));
+    }
+    paths
+}
+
+/// loops at ``distance`` around the outline, one per island
+pub fn skirt_loops(outline: &[ExPolygon], distance: f32) -> Vec<Polyline> {
+    offset::offset_expolygons(outline, distance, JoinType::Round)
+        .iter()
+        .map(|x| x.contour.split_at(0))
+        .collect()
+}
+
+/// convex corners of a counter clockwise contour with an inside angle
+/// under ``max_angle`` degrees
+fn sharp_corners(contour: &Polygon, max_angle: f32) -> Vec<Point> {
+    let points = &contour.points;
+    let n = points.len();
+    (0..n)
+        .filter_map(|i| {
+            let p = points[i];
+            let u = p - points[(i + n - 1) % n];
+            let v = points[(i + 1) % n] - p;
+            let cross = u.x * v.y - u.y * v.x;
+            if cross <= 0. {
+                return None;
+            }
+            let inside_angle = PI - cross.atan2(u.dot(&v));
+            (inside_angle.to_degrees() < max_angle).then_some(p)
+        })
+        .collect()
+}
@@ -0,0 +1,135 @@
+use crate::settings::FanSettings;
+use crate::slicer::toolpath::ExtrusionPath;
+
+/// part cooling fan speed for ``layer``, expected to print in
+/// ``layer_time`` seconds
+///
+/// The fan stays off for the first layers, then ramps up over the ramp
+/// layers. It runs at the min speed, raised towards the max speed as
+/// the layer time drops from the raise threshold to the min layer time.
+pub fn fan_speed(settings: &FanSettings, layer: usize, layer_time: f32) -> f32 {
+    let disabled = settings.disable_layer_count as usize;
+    if layer < disabled {
+        return 0.;
+    }
+    let (min, max) = (settings.min_speed, settings.max_speed);
+    let speed = if layer_time >= settings.raise_below_layer_time {
+        min
+    } else if settings.raise_below_layer_time > settings.min_layer_time {
+        let t = (settings.raise_below_layer_time - layer_time)
+            / (settings.raise_below_layer_time - settings.min_layer_time);
+        min + (max - min) * t.clamp(0., 1.)
+    } else {
+        max
+    };
+
+    let ramp = settings.ramp_layer_count as usize;
+    let k = layer - disabled;
+    if k < ramp {
+        speed * (k + 1) as f32 / (ramp + 1) as f32
+    } else {
+        speed
+    }
+}
+
+/// fan speed while printing bridges on ``layer``, none where they get
+/// the layer speed, which takes in the layers printed with the fan off
+pub fn bridge_fan_speed(settings: &FanSettings, layer: usize) -> Option<f32> {
+    if layer < settings.disable_layer_count as usize {
+        return None;
+    }
+    settings.bridge_speed
+}
+
+/// slow the paths down so the layer takes at least the min layer time,
+/// returns the factor the extruding feed rates were scaled by
+///
+/// Only extruding moves are slowed, travels keep their speed. No feed
+/// rate is brought under the min feed rate, so a small enough layer can
+/// still come out short.
+pub fn slow_down(paths: &mut [ExtrusionPath], settings: &FanSettings, extruding: f32, travelling: f32) -> f32 {
+    if extruding <= 0. || extruding + travelling >= settings.min_layer_time {
+        return 1.;
+    }
+    let factor = extruding / (settings.min_layer_time - travelling);
+    for path in paths.iter_mut() {
+        path.feed_rate = (path.feed_rate * factor).max(settings.min_feed_rate).min(path.feed_rate);
+    }
+    factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{Point, Polyline};
+    use crate::slicer::toolpath::PathRole;
+
+    fn fan() -> FanSettings {
+        FanSettings {
+            bridge_speed: Some(1.),
+            disable_layer_count: 2,
+            max_speed: 1.,
+            min_feed_rate: 600.,
+            min_layer_time: 10.,
+            min_speed: 0.2,
+            ramp_layer_count: 3,
+            raise_below_layer_time: 30.
+        }
+    }
+
+    fn path(feed_rate: f32) -> ExtrusionPath {
+        ExtrusionPath {
+            feed_rate,
+            layer_height: 0.2,
+            line_width: 0.4,
+            polyline: Polyline::new(vec![Point::new(0., 0.), Point::new(10., 0.)]),
+            role: PathRole::Infill
+        }
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn fan_off_on_the_first_layers() {
+        assert_eq!(fan_speed(&fan(), 0, 5.), 0.);
+        assert_eq!(fan_speed(&fan(), 1, 5.), 0.);
+        assert_eq!(bridge_fan_speed(&fan(), 1), None);
+        assert_eq!(bridge_fan_speed(&fan(), 2), Some(1.));
+    }
+
+    #[test]
+    fn fan_ramps_up_after_the_disabled_layers() {
+        assert_near(fan_speed(&fan(), 2, 60.), 0.2 / 4.);
+        assert_near(fan_speed(&fan(), 3, 60.), 0.2 * 2. / 4.);
+        assert_near(fan_speed(&fan(), 4, 60.), 0.2 * 3. / 4.);
+        assert_near(fan_speed(&fan(), 5, 60.), 0.2);
+        // the ramp scales a raised speed too
+        assert_near(fan_speed(&fan(), 2, 10.), 1. / 4.);
+    }
+
+    #[test]
+    fn fan_raised_for_quick_layers() {
+        assert_near(fan_speed(&fan(), 10, 30.), 0.2);
+        assert_near(fan_speed(&fan(), 10, 20.), 0.6);
+        assert_near(fan_speed(&fan(), 10, 15.), 0.8);
+        assert_near(fan_speed(&fan(), 10, 10.), 1.);
+        assert_near(fan_speed(&fan(), 10, 2.), 1.);
+    }
+
+    #[test]
+    fn slow_down_to_the_min_layer_time() {
+        // 4 s extruding and 2 s travelling need half the speed for 10 s
+        let mut paths = vec![path(2400.), path(900.), path(300.)];
+        assert_near(slow_down(&mut paths, &fan(), 4., 2.), 0.5);
+        assert_eq!(paths[0].feed_rate, 1200.);
+        // never under the min feed rate, nor faster than before
+        assert_eq!(paths[1].feed_rate, 600.);
+        assert_eq!(paths[2].feed_rate, 300.);
+
+        let mut paths = vec![path(2400.)];
+        assert_eq!(slow_down(&mut paths, &fan(), 8., 2.), 1.);
+        assert_eq!(paths[0].feed_rate, 2400.);
+    }
+}
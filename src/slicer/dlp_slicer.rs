@@ -1,6 +1,6 @@
 use crate::geometry::STLMesh;
 use crate::settings::Settings;
-use crate::slicer::Slicer;
+use crate::slicer::{layer_zs, Slicer};
 use nalgebra;
 
 
@@ -74,8 +74,9 @@ impl Slicer for DLPSlicer {
         println!("Total number of STL triangles = {}", self.stl_mesh.triangles().len());
         println!("Total number of voxel queries = {} million", zs.len() * grid.len() * self.stl_mesh.triangles().len() / 1000000);
         
-        // loop over layers
-        for (n, z) in zs.into_iter().enumerate() {
+        // loop over layers, cutting each half way up
+        let (_, slice_zs) = layer_zs(&zs, self.stl_mesh.bounding_box().z_min);
+        for (n, z) in slice_zs.into_iter().enumerate() {
             println!("Generating slice for layer {}", n);
             // let grid = self.planar_grid(z);
             let _ = self.layer_image(&grid, z);
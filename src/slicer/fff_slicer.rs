@@ -1,7 +1,17 @@
 use crate::gcode::GcodeWriter;
-use crate::geometry::{Edges, STLMesh};
-use crate::settings::{FloatOrVecOfFloats, Settings};
-use crate::slicer::Slicer;
+use crate::geometry::{clipping, contours, offset, ClipType, ExPolygon, JoinType, Point, Polygon, Polyline, STLMesh};
+use crate::settings::{FanSettings, InfillSettings, Settings, SupportStrategy};
+use crate::slicer::seam::SeamPlacer;
+use crate::slicer::toolpath::{ExtrusionPath, PathRole};
+use crate::slicer::{adhesion, cooling, infill, layer_zs, layers_to_reach, ordering, placement, support, toolpath, Slicer};
+
+/// feed rate of the z move between layers in mm/min when
+/// neither the travels nor the printer give one
+const LAYER_CHANGE_FEED_RATE: f32 = 1200.;
+
+/// corner style for walls, miters longer than this
+/// multiple of the offset get squared off
+const WALL_JOIN: JoinType = JoinType::Miter(3.0);
 
 pub struct FFFSlicer {
     settings: Settings,
@@ -14,373 +24,603 @@ impl FFFSlicer {
         stl_meshes: Vec<STLMesh>
     ) -> Self {
         Self {
-            settings: settings,
-            stl_meshes: stl_meshes
+            settings,
+            stl_meshes
         }
     }
 
-    pub fn perimeters(&self, stl: &STLMesh, gcode_writer: &mut GcodeWriter, z: f32) -> () {
-        let tol = 1e-6 as f32;
-        let tris = stl.triangles();
-        let perimeter_edges = Edges::new();
-        
-
-        // counter to track the index of the triangles that intersect with each z layer
-        let mut counter = 0;
-        // save the number of triangles per layer
-        let mut num_triangles = 0;
-        // temprorary vector that stores the intersecting triangle indices
-        let mut vec = vec![];
-        // vector containing the intersection coordinates between the 
-        // triangles and the plane z_height
-        let mut ab_coords = vec![];
-
-        // iterate through all the triangles in the stl
-        for tri in tris.iter() {
-
-            // break down vertices for easier handling (for me)
-            let vert_1 = tri[0];
-            let vert_2 = tri[1];
-            let vert_3 = tri[2];
-
-            
-
-            // if any of the vertices are above AND any are below, 
-            if vert_1[2] < z || vert_2[2] < z || vert_3[2] < z {
-                if vert_1[2] > z || vert_2[2] > z || vert_3[2] > z {
-                    
-                    // append the index of intersecting triangles
-                    vec.push(counter);
-
-                    // Must sort the vertices to be able to calculate
-                    // the line segment created by the interception of
-                    // the z_height and the triangles that bound it.
-                    // Assign signed integers to represent whether a
-                    // vertex of a triangle is above (+1), below (-1),
-                    // or on (0) the z_height plane.
-                    //
-                    // add all z vertices to vector
-                    let z_vec = [vert_1[2], vert_2[2], vert_3[2]];
-                    // empty vector for sorting
-                    let mut z_sort = vec![];
-                    for zS in z_vec {
-                        if zS - z < 0.0 {
-                            z_sort.push(-1);
-                        } else if zS - z > 0.0 {
-                            z_sort.push(1);
-                        } else {
-                            z_sort.push(0);
-                        }
-                    }
+    /// stitch the sweep of every plane in ``zs`` into layer regions
+    pub fn layer_regions(&self, stl: &STLMesh, zs: &[f32]) -> Vec<Vec<ExPolygon>> {
+        println!("Sweeping {} layers", zs.len());
+        stl.plane_sweep(zs)
+            .iter()
+            .zip(zs)
+            .map(|(segments, z)| {
+                let contours = contours::stitch(segments);
+                for chain in &contours.open {
+                    println!(
+                        "WARNING: open chain with {} points at z = {}, starting at {:?}",
+                        chain.points.len(), z, chain.points[0]
+                    );
+                }
+                ExPolygon::from_polygons(contours.closed)
+            })
+            .collect()
+    }
 
-                    // Test the relationship of the first two vertices,
-                    // with the goal of arranging the vertices to be
-                    // able to calculate the z intercept line segment.
-                    // There are three triangles possible: two vertices 
-                    // below z_height and one above, two vertices above
-                    // z_height and one below, and one vertex coincident
-                    // with z_height and one above and one below. In 
-                    // the first two cases, the single vertex will go into
-                    // the first index (0). In the last case, either 
-                    // vertex not coincident with z_height will go into
-                    // the first index (0). 
-                    //
-                    // Test the first two vertices by adding them. 
-                    // There are five possible values for sum_z0_z1:
-                    // 1) -2 the first and second vertices are below z_height
-                    // 2) -1 the first and second vertices are coincident and below z_height
-                    // 3)  0 the first and second vertices are below and above z_height
-                    // 4) +1 the first and second vertices are coincident and above z_height
-                    // 5) +2 the first and second vertices are above z_height
-                    // For cases 2, 3, and 4, further logic is required to 
-                    // deal with which vertex is coincident or if the third
-                    // vertex is above or below z_height. 
-                    let sum_z0_z1 = z_sort[0] + z_sort[1];
-                    let sum_z0_z2 = z_sort[0] + z_sort[2];
-
-                    // empty vector to store the properly sorted vertices
-                    let mut sorted_z = vec![]; 
-
-                    // are the first and second vertex on the same side?
-                    if sum_z0_z1 > 1 || sum_z0_z1 < -1 {
-                        // then the third vertex goes into index 0
-                        sorted_z = vec![2, 0, 1];
-                    
-                    // is the first or second vertex coincident with z_height?
-                    } else if sum_z0_z1 > 0 || sum_z0_z1 < 0 {
-                        // is the first vertex coincident with z_height?
-                        if z_sort[0] == 0 {
-                            // any other vertex goes in index 0
-                            sorted_z = vec![1, 0, 2];
-
-                        // then the second vertex is coincident with z_height
-                        } else {
-                            // original order acceptable
-                            sorted_z = vec![0, 1, 2];
-                        }
-                        
-                    // are the first and second vertices on opposite sides?
-                    } else if sum_z0_z1 == 0 {
-                        // is the third vertex coincident with z_height?
-                        if z_sort[2] == 0 {
-                            // order is fine
-                            sorted_z = vec![0, 1, 2];
-
-                        // is the third element the same as the first?
-                        } else if sum_z0_z2 > 0 || sum_z0_z2 < 0 {
-                            // the second element goes into index 0
-                            sorted_z = vec![1, 0, 2];
-                        // the first element is different than the second and third
-                        } else {
-                            // the order is acceptable
-                            sorted_z = vec![0, 1, 2];
-                        }
-                            
-                    }
+    /// part of the ``skin`` of layer ``n`` with nothing under it
+    ///
+    /// Slivers thinner than a line, left where the part grows a little
+    /// from layer to layer, are not bridges.
+    pub fn bridge_area(&self, skin: &[ExPolygon], layers: &[Vec<ExPolygon>], n: usize) -> Vec<ExPolygon> {
+        if n == 0 {
+            return vec![];
+        }
+        let line_width = self.line_width();
+        let bridges = clipping::boolean_ex(skin, &layers[n - 1], ClipType::Difference);
+        offset::open(&bridges, line_width, WALL_JOIN)
+    }
 
-                    
-                    // The z_height intersection will be composed of two points,
-                    // a and b. Calculate intersection points (xa, ya) and 
-                    // (xb, yb), and push them to a vector ab_coords.
-                    let z_factor_a = (z - tri[sorted_z[0]][2])/(tri[sorted_z[1]][2] - tri[sorted_z[0]][2]);
-                    let z_factor_b = (z - tri[sorted_z[0]][2])/(tri[sorted_z[2]][2] - tri[sorted_z[0]][2]);
-                    let xa = (z_factor_a * (tri[sorted_z[1]][0] - tri[sorted_z[0]][0])) + tri[sorted_z[0]][0];
-                    let ya = (z_factor_a * (tri[sorted_z[1]][1] - tri[sorted_z[0]][1])) + tri[sorted_z[0]][1];
-                    let xb = (z_factor_b * (tri[sorted_z[2]][0] - tri[sorted_z[0]][0])) + tri[sorted_z[0]][0];
-                    let yb = (z_factor_b * (tri[sorted_z[2]][1] - tri[sorted_z[0]][1])) + tri[sorted_z[0]][1];
-
-                    let a_coord = [xa, ya];
-                    let b_coord = [xb, yb];
-                    ab_coords.push([a_coord, b_coord]);
-                }
+    /// brim lines over ``area`` around the first layer ``regions``
+    pub fn brim(
+        &self,
+        regions: &[ExPolygon],
+        area: &[ExPolygon],
+        layer_height: f32
+    ) -> Vec<ExtrusionPath> {
+        let Some(settings) = &self.settings.brim else {
+            return vec![];
+        };
+        let line_width = self.line_width();
+        let paths = adhesion::brim_paths(regions, area, settings, line_width);
+        println!("Number of brim lines: {:?}", paths.len());
+        let feed_rate = self.perimeter_feed_rate(0);
+        self.extrusions(paths, layer_height, line_width, feed_rate, PathRole::Brim)
+    }
+
+    /// sparse infill over ``area``
+    pub fn infill(
+        &self,
+        area: &[ExPolygon],
+        layer: usize,
+        z: f32,
+        layer_height: f32
+    ) -> Vec<ExtrusionPath> {
+        let Some(settings) = &self.settings.infill else {
+            return vec![];
+        };
+        let line_width = self.line_width();
+        let paths = infill::infill_paths(area, settings, layer, z, line_width);
+        println!("Number of infill lines in layer: {:?}", paths.len());
+        self.extrusions(paths, layer_height, line_width, settings.feed_rate, PathRole::Infill)
+    }
+
+    /// area left for infill inside the innermost wall
+    ///
+    /// ``walls`` are the wall center lines, so the innermost one is
+    /// inset by another half line width to reach its inner edge, then
+    /// grown back by the overlap so the infill bonds to the wall.
+    pub fn infill_area(&self, regions: &[ExPolygon], walls: &[Vec<ExPolygon>]) -> Vec<ExPolygon> {
+        let line_width = self.line_width();
+        let overlap = self.settings.infill.as_ref().map_or(0., |x| x.overlap);
+        let inset = (overlap - 0.5) * line_width;
+        match walls.last() {
+            Some(wall) => offset::offset_expolygons(wall, inset, WALL_JOIN),
+            None => offset::offset_expolygons(regions, inset, WALL_JOIN)
+        }
+    }
+
+    /// lines to extrude, all alike
+    fn extrusions(
+        &self,
+        paths: Vec<Polyline>,
+        layer_height: f32,
+        line_width: f32,
+        feed_rate: f32,
+        role: PathRole
+    ) -> Vec<ExtrusionPath> {
+        paths
+            .into_iter()
+            .map(|polyline| ExtrusionPath { feed_rate, layer_height, line_width, polyline, role })
+            .collect()
+    }
+
+    /// part cooling settings of the material, if it wants the fan
+    fn fan_settings(&self) -> Option<&FanSettings> {
+        self.settings.material.material().and_then(|x| x.fan.as_ref())
+    }
+
+    /// fan speed for layer ``n``, after slowing the paths down to
+    /// take at least the min layer time
+    fn cool(&self, paths: &mut [ExtrusionPath], n: usize, from: Option<Point>) -> Option<f32> {
+        let settings = self.fan_settings()?;
+        let travel_feed_rate = self.settings.travel.as_ref().map(|x| x.feed_rate);
+        let (extruding, travelling) = toolpath::layer_time(paths, from, travel_feed_rate);
+        let speed = cooling::fan_speed(settings, n, extruding + travelling);
+        println!("Layer {} time estimate: {:.1} s, fan at {:.0}%", n, extruding + travelling, 100. * speed);
+        let factor = cooling::slow_down(paths, settings, extruding, travelling);
+        if factor < 1. {
+            println!("Slowing layer {} down to {:.0}% for the min layer time", n, 100. * factor);
+        }
+        Some(speed)
+    }
+
+    /// travel to the start of every path and extrude along it
+    ///
+    /// With a ``fan`` speed the fan is set to it, or to ``bridge_fan``
+    /// while printing bridges when there is one.
+    fn write_paths(
+        &self,
+        paths: &[ExtrusionPath],
+        gcode_writer: &mut GcodeWriter,
+        fan: Option<f32>,
+        bridge_fan: Option<f32>
+    ) {
+        for path in paths {
+            let start = path.start();
+            gcode_writer.write_travel(start.x, start.y);
+            match (path.role, bridge_fan, fan) {
+                (PathRole::Bridge, Some(speed), _) | (_, _, Some(speed)) => gcode_writer.write_fan(speed),
+                _ => {}
+            }
+            for point in path.polyline.points.iter().skip(1) {
+                gcode_writer.write_extrusion(point.x, point.y, path.layer_height, path.line_width, path.feed_rate);
             }
-            // increase counter for next triangle
-            counter += 1;
-            
-        } // end looping through all triangles for a layer at z_height
-        
+        }
+    }
+
+    fn line_width(&self) -> f32 {
+        self.settings.extrusion
+            .as_ref()
+            .expect("FFF slicing needs extrusion settings")
+            .line_width
+    }
+
+    pub fn perimeters(
+        &self,
+        walls: &[Vec<ExPolygon>],
+        layer: usize,
+        z: f32,
+        layer_height: f32,
+        seams: &mut SeamPlacer,
+        from: Option<Point>
+    ) -> Vec<ExtrusionPath> {
+        let line_width = self.line_width();
+        let feed_rate = self.perimeter_feed_rate(layer);
+
         println!("Global Layer height; {:?}", z);
-        println!("Number of triangles in Layer: {:?}", vec.len());
-        // println!("Sample AB-Coords: {:?}",ab_coords[0]);
-        // println!("Going Crazy: {:?}", ab_coords);
-        // Testing to sort the perimeter lines (ab)
-        let TEST = 1;
-        // if TEST == 1 { //16.5 {
-
-        // println!("All AB-Coords: {:?}", ab_coords);
-        
-        // perimeter array of the sorted line segments
-        let mut perimeter_sort = Vec::new();
-        // Clone the vector of all line segments, we 
-        // will be removing all used segments
-        let mut AB_COORDS = Vec::from(ab_coords.clone());
-        // push the first line segment to the sorted
-        // list, the exact place to start will need 
-        // to be adjusted in the future.
-        if AB_COORDS.len() > 0 {
-            perimeter_sort.push(AB_COORDS[0]);           
-            // remove the first line segment
-            AB_COORDS.swap_remove(0);
-            // create a counter variable
-            let mut num_available_segs = AB_COORDS.len();
-
-            // cycle through all line segments in AB_COORDS; if x_a and y_a of
-            // a line segment matches the x_b and y_b of the last segment in 
-            // perimeter_sort, then push that line segment to perimeter_sort. 
-            //
-            // TODO This doesn't delete the used segment.
-
-            // Search radius for next line segment.
-            let mut search_radius = 1.0e-3;
-
-            // while the number of available line segments is 
-            // above some number, keep building the connected
-            // lines
-            while num_available_segs > 0 {
-                // index counter, for the triangle in AB_COORDS
-                let mut index_count = 0;
-                // vector to save the matcing indices. This 
-                // needs to be a vector because there is a 
-                // chance the search radius is too big and
-                // finds more than one viable coordinate.
-                let mut index_match = vec![];
-                // does the order of the vector of coordinats 
-                // need to switched to properly follow the  
-                // perimeter
-                let mut vertex_switch = vec![];
-                // for each line segment in the layer, 
-                // compare the beginning (a) of a segment
-                // to the end of thelast line segment (b)
-                // in perimeter_sort vector. 
-                // 
-                // TODO THIS IS WRONG, it needs to look at 
-                // both points in each line segment, since
-                // the order of a and b is arbitrary. Oof.
-                for segs in AB_COORDS.iter() {
-                    // println!("Segments: {:?}", AB_COORDS);
-                    // println!("Last Segment: {:?}",perimeter_sort.last().expect("Perimeter is empty"));
-                    
-                    // find the distance between the last coordinate in perimeter_sort
-                    // and the current line segment, vertex a
-                    let mut x_diff_a = (segs[0][0] - perimeter_sort.last().expect("Perimeter is empty")[1][0]).abs();
-                    let mut y_diff_a = (segs[0][1] - perimeter_sort.last().expect("Perimeter is empty")[1][1]).abs();
-                    // ... same as above, but with vertex b
-                    let mut x_diff_b = (segs[1][0] - perimeter_sort.last().expect("Perimeter is empty")[1][0]).abs();
-                    let mut y_diff_b = (segs[1][1] - perimeter_sort.last().expect("Perimeter is empty")[1][1]).abs();
-                    
-                    // square the difference values
-                    let x_diff_a_square = x_diff_a.powi(2);
-                    let y_diff_a_square = y_diff_a.powi(2);
-                    let x_diff_b_square = x_diff_b.powi(2);
-                    let y_diff_b_square = y_diff_b.powi(2);
-                    // sum the square differences
-                    let diff_a_square_sum = x_diff_a_square + y_diff_a_square;
-                    let diff_b_square_sum = x_diff_b_square + y_diff_b_square;
-                    // calculate the euclidean distance between
-                    // the a and b verices and the endpoint of
-                    // the perimeter line vector.
-                    let euclidean_a = diff_a_square_sum.sqrt();
-                    let euclidean_b = diff_b_square_sum.sqrt();
-                    
-                    // let mut x_diff_bits: u64 = x_diff.to_bits();
-                    // let mut y_diff_bits: u64 = y_diff.to_bits();
-                    // x_diff_bits = x_diff_bits * x_diff_bits as u64;
-                    // y_diff_bits = y_diff_bits * y_diff_bits as u64;
-                    // let diff_square_sum = x_diff_bits + y_diff_bits;
-                    // let mut euclidean = f32::from_bits(diff_square_sum);
-                    
-                    // Only use the closer of the two euclidean values
-                    //
-                    // TODO Need to decide which euclidean is smaller
-                    // and pass those values to the search.
-
-                    // check which vertex is closer to the last point 
-                    // in the perimeter. Assign the respective
-                    // euclidean value and a 0 or 1 for which is 
-                    // closer (this is for rearranging outside this
-                    // loop).
-                    let mut which_vertex: f32;
-                    let mut smaller_euclidean: f32;
-                    if euclidean_a > euclidean_b {
-                        smaller_euclidean = euclidean_b;
-                        which_vertex = 1.0;
-                    } else {
-                        smaller_euclidean = euclidean_a;
-                        which_vertex = 0.0;
-                    }
+        println!("Number of walls in Layer: {:?}", walls.len());
 
-                    
-                    // let smaller_euclidean = euclidean_a;
-                    let euclidean = smaller_euclidean;
-                    // println!("euclidean A: {:?}",euclidean_a);
-                    // println!("euclidean B: {:?}",euclidean_b);
-                    // println!("euclidean  : {:?}", euclidean);
-                    // println!("Search Radius: {:?}", search_radius);
-                    if euclidean <= search_radius  {
-                        // if y_diff <= search_radius {
-                        // println!("--------------------------------------");
-                        // println!("Match Found");
-                        // println!("euclindean A: {:?}",euclidean_b);
-                        // println!("euclindean B: {:?}",euclidean_b);
-                        // println!("euclindean B: {:?}",euclidean_b);
-                        // println!("Segment: {:?}", segs);
-                        // println!("Segment X: {:?}, Segment Y: {:?}", segs[0][0], segs[0][1]);
-                        // println!("Last Perimeter Segment: {:?}", perimeter_sort.last().expect("Perimeter is empty")[1]);
-                        // println!("Searched X: {:?}, Searched Y: {:?}", perimeter_sort.last().expect("Perimeter is empty")[1][0], perimeter_sort.last().expect("Perimeter is empty")[1][1]);
-                        // println!("X diff: {:?}",x_diff);
-                        // println!("Y diff: {:?}",y_diff);
-                        // println!("Euclidean: {:?}",euclidean);
-                        // println!("Search Radius: {:?}", search_radius);
-                        // perimeter_sort.push(*segs);
-                        index_match.push(index_count);
-                        vertex_switch.push(which_vertex);
-                        // }
-                    }
-                    index_count += 1;
+        // outer walls first, the loops of each wall nearest first, and
+        // since a loop ends where it starts the next one comes from its seam
+        let mut position = from;
+        let mut loops = vec![];
+        for wall in walls {
+            let mut polygons: Vec<&Polygon> = wall
+                .iter()
+                .flat_map(|x| x.polygons())
+                .filter(|x| !x.is_empty())
+                .collect();
+            while !polygons.is_empty() {
+                let next = match position {
+                    Some(p) => (0..polygons.len())
+                        .min_by(|&a, &b| {
+                            let a = polygons[a].closest_point(&p).map_or(f32::INFINITY, |(_, x)| (x - p).norm());
+                            let b = polygons[b].closest_point(&p).map_or(f32::INFINITY, |(_, x)| (x - p).norm());
+                            a.total_cmp(&b)
+                        })
+                        .unwrap(),
+                    None => 0
+                };
+                let seam = seams.open(polygons.remove(next), position);
+                position = Some(seam.points[0]);
+                loops.push(seam);
+            }
+        }
+        self.extrusions(loops, layer_height, line_width, feed_rate, PathRole::Wall)
+    }
+
+    /// raft under the first layer, returns how high the part starts
+    ///
+    /// Every raft layer is a loop around the raft followed by lines
+    /// turning a quarter from layer to layer. The skirt goes around the
+    /// first raft layer, a brim would sit on the raft and is dropped.
+    pub fn raft(&self, first_layer: &[ExPolygon], gcode_writer: &mut GcodeWriter) -> f32 {
+        let Some(settings) = &self.settings.raft else {
+            return 0.;
+        };
+        if self.settings.brim.is_some() {
+            println!("WARNING: no brim is printed on top of a raft");
+        }
+        let line_width = self.line_width();
+        let area = offset::offset_expolygons(first_layer, settings.margin, JoinType::Round);
+        let outline: Vec<Polyline> = offset::offset_expolygons(&area, -0.5 * line_width, WALL_JOIN)
+            .iter()
+            .flat_map(|x| x.polygons())
+            .map(|x| x.split_at(0))
+            .collect();
+        let fill = offset::offset_expolygons(&area, -line_width, WALL_JOIN);
+
+        let stack = [
+            (settings.base_layer_count, settings.base_layer_height, settings.base_line_spacing),
+            (settings.interface_layer_count, settings.interface_layer_height, settings.interface_line_spacing),
+            (settings.surface_layer_count, settings.surface_layer_height, line_width)
+        ];
+        let mut z = 0.;
+        let mut n = 0;
+        for (count, layer_height, spacing) in stack {
+            for _ in 0..count {
+                z += layer_height;
+                println!("Generating raft layer {}", n);
+                gcode_writer.write_raft_layer_change(n, z, settings.feed_rate);
+                let mut paths = vec![];
+                if n == 0 {
+                    paths.extend(self.skirt(&area, gcode_writer, layer_height));
                 }
+                let lines = infill::scanlines(&fill, 90. * (n % 2) as f32, spacing, 0.);
+                let feed_rate = settings.feed_rate;
+                paths.extend(self.extrusions(outline.clone(), layer_height, line_width, feed_rate, PathRole::Support));
+                paths.extend(self.extrusions(lines, layer_height, line_width, feed_rate, PathRole::Support));
+                self.write_paths(&paths, gcode_writer, None, None);
+                n += 1;
+            }
+        }
+        z + settings.air_gap
+    }
 
-                // The search radius may result in one, more than one, or 
-                // no matches found. If one is found, we consider it a true match
-                // and pass it through to the ordered perimeter_sort vector. If
-                // more than one is found, shrink the search radius, and repeat
-                // the search. If none are found, grow the search radus, and
-                // repeat the search. (Hopefully).
-                //
-                // println!("NUM INDEXES: {:?}",index_match.len());
-                // println!("INDEXES: {:?}",index_match);
-                // println!("----------------------------------");
-                if index_match.len() == 1 {
-                    perimeter_sort.push(AB_COORDS[index_match[0]]);
-                    // println!("Size of index match: {:?}",index_match.len());
-                    // AB_COORDS.swap_remove(index_count);
-                    // println!("Test index match: {:?}", index_match);
-                    // println!("Size of AB_COUNT: {:?}",AB_COORDS.len());
-                    // println!("Sorted Segments: {:?}",perimeter_sort);
-                    num_available_segs -= 1;
-                    let REMOVE = *index_match.last().expect("Nothing in the perimeter");
-                    AB_COORDS.swap_remove(REMOVE);
-                    // println!("Size of AB_COUNT: {:?}",AB_COORDS.len());
-                    search_radius = 1e-3;
-                    gcode_writer.write_perimeter(perimeter_sort.last().unwrap()[1][0],perimeter_sort.last().unwrap()[1][1],z,555.0,1200.0);
-                } else if index_match.len() > 1 {
-                    search_radius = search_radius * 0.9;
-                } else {
-                    search_radius = search_radius * 1.1;
+    /// solid lines over ``area``, turning a quarter every layer
+    ///
+    /// Rows are laid one after the other from one side to the other,
+    /// so neighbouring lines always flow into each other the same way
+    /// and the surface comes out even.
+    pub fn skin(
+        &self,
+        area: &[ExPolygon],
+        layer: usize,
+        layer_height: f32,
+        role: PathRole
+    ) -> Vec<ExtrusionPath> {
+        let Some(settings) = &self.settings.skin else {
+            return vec![];
+        };
+        let line_width = self.line_width();
+        let angle = settings.line_angle + 90. * (layer % 2) as f32;
+        let paths = infill::scanlines(area, angle, line_width, 0.);
+        println!("Number of {:?} lines in layer: {:?}", role, paths.len());
+        self.extrusions(paths, layer_height, line_width, settings.feed_rate, role)
+    }
+
+    /// part of the infill ``area`` of layer ``n`` that is top or bottom skin
+    ///
+    /// Whatever is not covered by every one of the next few layers up is
+    /// top skin, and likewise going down for bottom skin. Near either
+    /// end of the stack the whole area is skin. Slivers thinner than a
+    /// line, left where a slanted wall moves a little from layer to
+    /// layer, are dropped.
+    pub fn skin_area(
+        &self,
+        regions: &[Vec<ExPolygon>],
+        heights: &[f32],
+        n: usize,
+        area: &[ExPolygon]
+    ) -> Vec<ExPolygon> {
+        let Some(settings) = &self.settings.skin else {
+            return vec![];
+        };
+        let top = layers_to_reach(
+            heights[n + 1..].iter(), settings.top_layer_count, settings.min_thickness
+        );
+        let bottom = layers_to_reach(
+            heights[..n].iter().rev(), settings.bottom_layer_count, settings.min_thickness
+        );
+        let (Some(top), Some(bottom)) = (top, bottom) else {
+            return area.to_vec();
+        };
+
+        let mut covered: Option<Vec<ExPolygon>> = None;
+        for k in (n + 1..=n + top).chain(n - bottom..n) {
+            covered = Some(match covered {
+                None => regions[k].clone(),
+                Some(x) => clipping::boolean_ex(&x, &regions[k], ClipType::Intersection)
+            });
+        }
+        let Some(covered) = covered else {
+            return vec![];
+        };
+        let line_width = self.line_width();
+        let skin = clipping::boolean_ex(area, &covered, ClipType::Difference);
+        offset::open(&skin, line_width, WALL_JOIN)
+    }
+
+    /// skirt loops around ``outline``, outermost first
+    ///
+    /// Loops are added past the configured count until they use at
+    /// least the minimum length of filament.
+    pub fn skirt(&self, outline: &[ExPolygon], gcode_writer: &GcodeWriter, layer_height: f32) -> Vec<ExtrusionPath> {
+        let Some(settings) = &self.settings.skirt else {
+            return vec![];
+        };
+        let line_width = self.line_width();
+        let min_e = gcode_writer.filament_to_e(settings.min_length);
+
+        let mut loops = vec![];
+        let mut e = 0.;
+        while loops.len() < settings.line_count as usize || e < min_e {
+            let distance = settings.distance + (loops.len() as f32 + 0.5) * line_width;
+            let skirt_loop = adhesion::skirt_loops(outline, distance);
+            if skirt_loop.is_empty() {
+                break;
+            }
+            e += skirt_loop
+                .iter()
+                .map(|x| gcode_writer.extrusion_length(x.length(), line_width, layer_height))
+                .sum::<f64>();
+            loops.push(skirt_loop);
+        }
+        println!("Number of skirt loops: {:?}", loops.len());
+        let feed_rate = self.perimeter_feed_rate(0);
+        let loops: Vec<Polyline> = loops.into_iter().rev().flatten().collect();
+        self.extrusions(loops, layer_height, line_width, feed_rate, PathRole::Skirt)
+    }
+
+    /// support over ``area``, solid where it is ``interface``
+    pub fn support(
+        &self,
+        area: &[ExPolygon],
+        interface: &[ExPolygon],
+        layer: usize,
+        z: f32,
+        layer_height: f32
+    ) -> Vec<ExtrusionPath> {
+        let Some(settings) = &self.settings.support else {
+            return vec![];
+        };
+        let line_width = self.line_width();
+        if settings.strategy == SupportStrategy::Tree {
+            // two loops around every branch
+            let paths: Vec<Polyline> = [0.5_f32, 1.5]
+                .iter()
+                .flat_map(|x| offset::offset_expolygons(area, -x * line_width, WALL_JOIN))
+                .flat_map(|x| x.polygons().map(|x| x.split_at(0)).collect::<Vec<_>>())
+                .collect();
+            println!("Number of tree support loops in layer: {:?}", paths.len());
+            return self.extrusions(paths, layer_height, line_width, settings.feed_rate, PathRole::Support);
+        }
+        let sparse = clipping::boolean_ex(area, interface, ClipType::Difference);
+        let infill_settings = InfillSettings {
+            density: settings.density,
+            feed_rate: settings.feed_rate,
+            line_angle: settings.line_angle,
+            layer_rotation: 0.,
+            overlap: 0.,
+            pattern: settings.pattern
+        };
+        let mut paths = infill::scanlines(interface, settings.line_angle + 90., line_width, 0.);
+        paths.extend(infill::infill_paths(&sparse, &infill_settings, layer, z, line_width));
+        println!("Number of support lines in layer: {:?}", paths.len());
+        self.extrusions(paths, layer_height, line_width, settings.feed_rate, PathRole::Support)
+    }
+
+    /// support and support interface areas of every layer
+    ///
+    /// Layers are only checked for overhangs where overhanging faces of
+    /// one of the meshes pass between them and the layer below.
+    pub fn support_areas(
+        &self,
+        layers: &[Vec<ExPolygon>],
+        heights: &[f32],
+        print_zs: &[f32],
+        slice_zs: &[f32]
+    ) -> (Vec<Vec<ExPolygon>>, Vec<Vec<ExPolygon>>) {
+        let Some(settings) = &self.settings.support else {
+            return (vec![vec![]; layers.len()], vec![vec![]; layers.len()]);
+        };
+        let mut flagged = vec![false; layers.len()];
+        for stl in &self.stl_meshes {
+            for face in stl.overhanging_faces(settings.angle) {
+                let triangle = stl.triangles()[face];
+                let z_min = triangle.iter().map(|x| x[2]).fold(f32::MAX, f32::min);
+                let z_max = triangle.iter().map(|x| x[2]).fold(f32::MIN, f32::max);
+                for n in 1..layers.len() {
+                    if z_min <= print_zs[n] && z_max >= print_zs[n - 1] {
+                        flagged[n] = true;
+                    }
                 }
-                
             }
-            // println!("Sorted Segments: {:?}",perimeter_sort);
-            
-            println!("----------------------------------");
+        }
+
+        println!("Generating support");
+        let overhangs = support::overhangs(layers, heights, &flagged, settings, self.line_width());
+        if settings.strategy == SupportStrategy::Tree {
+            let support = support::tree_regions(
+                &self.stl_meshes, layers, &overhangs, heights, slice_zs, settings
+            );
+            return (support, vec![vec![]; layers.len()]);
+        }
+        let support = support::support_regions(layers, &overhangs, heights, settings);
+        let interface = support::interface_regions(&support, settings.interface_layer_count);
+        (support, interface)
+    }
+
+    /// concentric walls for a layer, outermost first
+    ///
+    /// The outer wall is inset by half a line width so the outside of
+    /// the extruded line lands on the model surface, every further wall
+    /// one line width deeper. Walls stop early where the part is too
+    /// thin to fit them.
+    pub fn walls(&self, regions: &[ExPolygon], layer: usize) -> Vec<Vec<ExPolygon>> {
+        let line_width = self.line_width();
+        let wall_line_count = self.wall_line_count(layer);
+
+        let mut walls = vec![];
+        let mut wall = offset::offset_expolygons(regions, -0.5 * line_width, WALL_JOIN);
+        for _ in 0..wall_line_count {
+            if wall.is_empty() {
+                break;
+            }
+            let next = offset::offset_expolygons(&wall, -line_width, WALL_JOIN);
+            walls.push(wall);
+            wall = next;
+        }
+        walls
+    }
 
+    /// as fast as the travels, but no faster than the printer moves z
+    fn layer_change_feed_rate(&self) -> f32 {
+        let travel = self.settings.travel.as_ref().map(|x| x.feed_rate);
+        let z_max = self.settings.printer
+            .as_ref()
+            .and_then(|x| x.max_feed_rates.as_ref())
+            .map(|x| x.z);
+        match (travel, z_max) {
+            (Some(travel), Some(z_max)) => travel.min(z_max),
+            (Some(travel), None) => travel,
+            (None, Some(z_max)) => z_max,
+            (None, None) => LAYER_CHANGE_FEED_RATE
+        }
+    }
 
-            
+    fn perimeter_feed_rate(&self, layer: usize) -> f32 {
+        let perimeter = self.settings.perimeter
+            .as_ref()
+            .expect("FFF slicing needs perimeter settings");
+        if layer == 0 {
+            return perimeter.layer_0_feed_rate;
         }
-        
-        // Sort the ab_coords per layer to produce the perimeter
-        // ouline paths. 
-        // Must remove used coord pairs from available list in
-        // order to check if multiple print "islands" occure per
-        // layer.
-        
+        perimeter.layer_n_feed_rate.for_layer(layer)
+    }
 
+    fn wall_line_count(&self, layer: usize) -> u32 {
+        let perimeter = self.settings.perimeter
+            .as_ref()
+            .expect("FFF slicing needs perimeter settings");
+        if layer == 0 {
+            return perimeter.layer_0_wall_line_count;
+        }
+        perimeter.layer_n_wall_line_count
+            .for_layer(layer)
+            .try_into()
+            .expect("Wall line counts can not be negative")
     }
 }
 
 impl Slicer for FFFSlicer {
     fn slice(&self, gcode_file: &str) {
-        let mut gcode_writer = GcodeWriter::new(gcode_file);
+        // a part that does not fit leaves no g-code file behind
+        if let Some(printer) = &self.settings.printer {
+            placement::check_build_volume(&self.stl_meshes, printer, self.settings.raft.as_ref());
+        }
+        let mut gcode_writer = GcodeWriter::new(gcode_file, &self.settings);
         gcode_writer.write_header(&self.settings);
 
+        println!("{}", self.settings);
+        println!("Generating layer heights");
+        // every mesh shares the layer stack of the tallest one
+        let tallest = self.stl_meshes
+            .iter()
+            .max_by(|a, b| a.bounding_box().z_max.total_cmp(&b.bounding_box().z_max))
+            .expect("Need at least one stl file");
+        let zs = self.layer_heights(&self.settings, tallest);
+        let z_min = self.stl_meshes
+            .iter()
+            .map(|x| x.bounding_box().z_min)
+            .fold(f32::MAX, f32::min);
+        let (print_zs, slice_zs) = layer_zs(&zs, z_min);
+
+        let mut layers = vec![vec![]; slice_zs.len()];
         for stl in &self.stl_meshes {
             println!("Slicing stl file {:?}", stl.file_name());
-            println!("{}", self.settings);
-            println!("Generating layer heights");
-            let zs = self.layer_heights(&self.settings, &stl);
-            let mut z_height: f32 = 0.0;
-            // TODO must shift the stl up in the z direction, no negatives
-            // let z_offset = -1.0 * -stl.bounding_box().z_min;
-            // let _ = stl.translate(0.0,0.0,z_offset);
-            for (n, z) in zs.iter().enumerate() {
-                
-                let positioning = 0;
-
-                if positioning == 0 {
-                    z_height = z_height + *z;
+            for (layer, regions) in layers.iter_mut().zip(self.layer_regions(stl, &slice_zs)) {
+                layer.push(regions);
+            }
+        }
+
+        // overlapping meshes are merged so shared volume prints once
+        let layers: Vec<Vec<ExPolygon>> = layers
+            .into_iter()
+            .map(|mesh_regions| {
+                if mesh_regions.len() == 1 {
+                    mesh_regions.into_iter().next().unwrap()
                 } else {
-                    z_height = *z;
+                    clipping::boolean_ex(&mesh_regions.concat(), &[], ClipType::Union)
+                }
+            })
+            .collect();
+
+        let perimeter = self.settings.perimeter
+            .as_ref()
+            .expect("FFF slicing needs perimeter settings");
+        let mut seams = SeamPlacer::new(perimeter.seam, perimeter.seam_point);
+
+        let (support, interface) = self.support_areas(&layers, &zs, &print_zs, &slice_zs);
+        let z_offset = self.raft(&layers[0], &mut gcode_writer);
+        let (mut total_travel, mut total_unordered) = (0., 0.);
+
+        for (n, (z, regions)) in zs.iter().zip(&layers).enumerate() {
+            let (print_z, slice_z) = (print_zs[n], slice_zs[n]);
+            gcode_writer.write_layer_change(
+                n.try_into().unwrap(), print_z + z_offset, 'F', self.layer_change_feed_rate()
+            );
+            // planned paths are printed one after the other, the first
+            // starting where the last layer ended
+            let start = gcode_writer.position().map(|[x, y]| Point::new(x, y));
+            let end_of = |paths: &[ExtrusionPath]| paths.last().map(|x| x.end()).or(start);
+            let mut paths = vec![];
+            if n == 0 && self.settings.raft.is_none() {
+                let brim_area = match &self.settings.brim {
+                    Some(brim) => adhesion::brim_area(regions, brim),
+                    None => vec![]
+                };
+                let outline = clipping::boolean_ex(regions, &brim_area, ClipType::Union);
+                paths.extend(self.skirt(&outline, &gcode_writer, *z));
+                paths.extend(self.brim(regions, &brim_area, *z));
+            }
+            // as generated, to see what ordering saves
+            let mut generated = vec![vec![]; regions.len() + 1];
+            generated[0] = paths.clone();
+            let support_paths = self.support(&support[n], &interface[n], n, slice_z, *z);
+            generated[0].extend(support_paths.iter().cloned());
+            paths.extend(ordering::order_paths(support_paths, end_of(&paths)));
+            println!("Generating perimeters for layer {}", n);
+            println!("Layer heigh: {:?}",*z);
+
+            // islands one at a time, walls first and then what they enclose
+            for i in ordering::order_islands(regions, end_of(&paths)) {
+                let island = std::slice::from_ref(&regions[i]);
+                let walls = self.walls(island, n);
+                let wall_paths = self.perimeters(&walls, n, print_z, *z, &mut seams, end_of(&paths));
+                generated[i + 1].extend(wall_paths.iter().cloned());
+                paths.extend(wall_paths);
+                let area = self.infill_area(island, &walls);
+                let skin = self.skin_area(&layers, &zs, n, &area);
+                let sparse = clipping::boolean_ex(&area, &skin, ClipType::Difference);
+                let bridges = self.bridge_area(&skin, &layers, n);
+                let skin = clipping::boolean_ex(&skin, &bridges, ClipType::Difference);
+                // solid rows keep their order, sparse infill is free to move
+                for group in [
+                    self.skin(&skin, n, *z, PathRole::Skin),
+                    self.skin(&bridges, n, *z, PathRole::Bridge)
+                ] {
+                    generated[i + 1].extend(group.iter().cloned());
+                    paths.extend(ordering::order_rows(group, end_of(&paths)));
                 }
-                
-                 // height of print plane
-                gcode_writer.write_layer_change(
-                    n.try_into().unwrap(), z_height, 'F', 1200. // TODO
-                );
-                // TODO skirt/brim
-                println!("Generating perimeters for layer {}", n);
-                println!("Layer heigh: {:?}",*z);
-                let _ = self.perimeters(&stl, &mut gcode_writer, z_height);
-                // TODO infill
+                let infill = self.infill(&sparse, n, slice_z, *z);
+                generated[i + 1].extend(infill.iter().cloned());
+                paths.extend(ordering::order_paths(infill, end_of(&paths)));
             }
+            seams.next_layer();
+
+            let travel = toolpath::travel_distance(&paths, start);
+            let unordered = toolpath::travel_distance(&generated.concat(), start);
+            println!("Layer {} travel: {:.1} mm, {:.1} mm unordered", n, travel, unordered);
+            total_travel += travel;
+            total_unordered += unordered;
+
+            let fan = self.cool(&mut paths, n, start);
+            let bridge_fan = self.fan_settings().and_then(|x| cooling::bridge_fan_speed(x, n));
+            self.write_paths(&paths, &mut gcode_writer, fan, bridge_fan);
         }
+        println!("Total travel: {:.1} mm, {:.1} mm unordered", total_travel, total_unordered);
+        gcode_writer.write_end();
     }
 }
@@ -0,0 +1,270 @@
+use crate::geometry::{clipping, BoundingBox2D, ExPolygon, Point, Polyline};
+use crate::settings::{InfillPattern, InfillSettings};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+/// sparse infill paths for one layer at height ``z``
+///
+/// ``regions`` is the area the infill lines may cover, already inset
+/// past the walls. Lines are spaced so that, summed over every line
+/// direction of the pattern, the requested density of the area gets
+/// covered by lines of ``line_width``. The 3D patterns depend on ``z``
+/// and ignore the per layer rotation, which would break them apart.
+pub fn infill_paths(
+    regions: &[ExPolygon],
+    settings: &InfillSettings,
+    layer: usize,
+    z: f32,
+    line_width: f32
+) -> Vec<Polyline> {
+    if regions.is_empty() || settings.density <= 0. {
+        return vec![];
+    }
+    let density = settings.density.min(1.);
+    let angle = settings.line_angle + layer as f32 * settings.layer_rotation;
+    let directions: Vec<f32> = match settings.pattern {
+        InfillPattern::Cubic => {
+            let angle = settings.line_angle;
+            vec![angle, angle + 120., angle + 240.]
+        },
+        InfillPattern::Diamond | InfillPattern::Gyroid | InfillPattern::SchwarzP => {
+            return tpms(regions, settings.pattern, z, line_width, density);
+        },
+        InfillPattern::Grid => vec![angle, angle + 90.],
+        InfillPattern::Lines => vec![angle],
+        InfillPattern::Triangles => vec![angle, angle + 60., angle + 120.]
+    };
+    let spacing = directions.len() as f32 * line_width / density;
+    // planes of a cube standing on its corner are tilted so their
+    // section moves sideways by z / sqrt(2) per unit of height
+    let shift = match settings.pattern {
+        InfillPattern::Cubic => z / 2_f32.sqrt(),
+        _ => 0.
+    };
+
+    let mut paths = vec![];
+    for direction in directions {
+        paths.extend(scanlines(regions, direction, spacing, shift));
+    }
+    paths
+}
+
+/// lines at ``angle`` degrees from the x axis, ``spacing`` apart,
+/// clipped to the regions
+///
+/// Lines are anchored to the origin rather than the regions, so the
+/// same line positions repeat from layer to layer and lines stack on
+/// top of each other. ``shift`` moves every line sideways, to the left
+/// of its direction. Consecutive lines run in opposite directions.
+pub fn scanlines(regions: &[ExPolygon], angle: f32, spacing: f32, shift: f32) -> Vec<Polyline> {
+    // rotate the regions so the lines become horizontal
+    let (sin, cos) = (-angle.to_radians()).sin_cos();
+    let rotate = |p: &Point, sin: f32| Point::new(p.x * cos - p.y * sin, p.x * sin + p.y * cos);
+    let edges: Vec<(Point, Point)> = regions
+        .iter()
+        .flat_map(|x| x.polygons())
+        .flat_map(|x| x.edges())
+        .map(|(a, b)| (rotate(a, sin), rotate(b, sin)))
+        .collect();
+
+    let y_min = edges.iter().map(|x| x.0.y).fold(f32::MAX, f32::min);
+    let y_max = edges.iter().map(|x| x.0.y).fold(f32::MIN, f32::max);
+
+    let mut lines = vec![];
+    let shift = shift.rem_euclid(spacing);
+    let mut n = ((y_min - shift) / spacing).ceil() as i64;
+    let mut forward = true;
+    while n as f32 * spacing + shift <= y_max {
+        let y = n as f32 * spacing + shift;
+        n += 1;
+
+        // half open rule so a line through a vertex counts it once
+        let mut xs: Vec<f32> = edges
+            .iter()
+            .filter(|(a, b)| (a.y > y) != (b.y > y))
+            .map(|(a, b)| a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x))
+            .collect();
+        if xs.len() < 2 {
+            continue;
+        }
+        xs.sort_by(|a, b| a.total_cmp(b));
+
+        let mut row: Vec<Polyline> = xs
+            .chunks_exact(2)
+            .filter(|x| x[1] > x[0])
+            .map(|x| {
+                let points = if forward {
+                    vec![Point::new(x[0], y), Point::new(x[1], y)]
+                } else {
+                    vec![Point::new(x[1], y), Point::new(x[0], y)]
+                };
+                Polyline::new(points)
+            })
+            .collect();
+        if !forward {
+            row.reverse();
+        }
+        lines.extend(row);
+        forward = !forward;
+    }
+
+    // and back into place
+    for line in lines.iter_mut() {
+        for p in line.points.iter_mut() {
+            *p = rotate(p, -sin);
+        }
+    }
+    lines
+}
+
+/// section of a triply periodic minimal surface at height ``z``,
+/// clipped to the regions
+///
+/// The implicit function is sampled on a square grid over the regions
+/// and its zero level traced with marching squares. The period is
+/// picked so the length of line laid down per area matches the
+/// density, that is the surface area per volume times the line width,
+/// scaled by pi / 4 since on average the surface crosses a layer at a
+/// slant and the section is shorter than the surface is wide.
+pub fn tpms(
+    regions: &[ExPolygon],
+    pattern: InfillPattern,
+    z: f32,
+    line_width: f32,
+    density: f32
+) -> Vec<Polyline> {
+    // surface area of one unit cell of each surface
+    let (cell_area, f): (f32, fn(f32, f32, f32) -> f32) = match pattern {
+        InfillPattern::Diamond => (3.8387, diamond),
+        InfillPattern::Gyroid => (3.0971, gyroid),
+        InfillPattern::SchwarzP => (2.3451, schwarz_p),
+        _ => panic!("{:?} is not a surface pattern", pattern)
+    };
+    let period = PI / 4. * cell_area * line_width / density;
+    let scale = 2. * PI / period;
+
+    let bounding_box = regions
+        .iter()
+        .map(|x| x.bounding_box())
+        .reduce(|a, b| a.union(&b))
+        .unwrap();
+    // half a line width is plenty to follow the curves once simplified
+    let step = (line_width / 2.).min(period / 8.);
+    let lines = marching_squares(&bounding_box, step, |x, y| f(x * scale, y * scale, z * scale));
+    let lines: Vec<Polyline> = lines
+        .iter()
+        .map(|x| x.simplify(0.05 * line_width))
+        .collect();
+    clipping::clip_polylines(&lines, regions)
+}
+
+fn diamond(x: f32, y: f32, z: f32) -> f32 {
+    let (sx, cx) = x.sin_cos();
+    let (sy, cy) = y.sin_cos();
+    let (sz, cz) = z.sin_cos();
+    sx * sy * sz + sx * cy * cz + cx * sy * cz + cx * cy * sz
+}
+
+fn gyroid(x: f32, y: f32, z: f32) -> f32 {
+    x.sin() * y.cos() + y.sin() * z.cos() + z.sin() * x.cos()
+}
+
+fn schwarz_p(x: f32, y: f32, z: f32) -> f32 {
+    x.cos() + y.cos() + z.cos()
+}
+
+/// grid edge a contour crosses, the lower left grid point and whether
+/// the edge runs along x
+type GridEdge = (usize, usize, bool);
+
+/// zero level lines of ``f`` over the box, joined into polylines
+///
+/// Closed lines come back with the first point repeated at the end.
+fn marching_squares<F>(bounding_box: &BoundingBox2D, step: f32, f: F) -> Vec<Polyline>
+where
+    F: Fn(f32, f32) -> f32
+{
+    let nx = ((bounding_box.x_max - bounding_box.x_min) / step).ceil() as usize + 1;
+    let ny = ((bounding_box.y_max - bounding_box.y_min) / step).ceil() as usize + 1;
+    let point = |i: usize, j: usize| {
+        Point::new(bounding_box.x_min + i as f32 * step, bounding_box.y_min + j as f32 * step)
+    };
+    let values: Vec<Vec<f32>> = (0..=nx)
+        .map(|i| (0..=ny).map(|j| {
+            let p = point(i, j);
+            f(p.x, p.y)
+        }).collect())
+        .collect();
+
+    // where the zero level crosses a grid edge
+    let crossing = |(i, j, along_x): GridEdge| {
+        let (i2, j2) = if along_x { (i + 1, j) } else { (i, j + 1) };
+        let (a, b) = (values[i][j], values[i2][j2]);
+        let t = a / (a - b);
+        point(i, j) + (point(i2, j2) - point(i, j)) * t
+    };
+
+    let mut segments: Vec<(GridEdge, GridEdge)> = vec![];
+    for i in 0..nx {
+        for j in 0..ny {
+            let corners = [values[i][j], values[i + 1][j], values[i + 1][j + 1], values[i][j + 1]];
+            // bottom, right, top, left
+            let edges = [(i, j, true), (i + 1, j, false), (i, j + 1, true), (i, j, false)];
+            let crossed: Vec<GridEdge> = (0..4)
+                .filter(|&k| (corners[k] >= 0.) != (corners[(k + 1) % 4] >= 0.))
+                .map(|k| edges[k])
+                .collect();
+            match crossed.len() {
+                2 => segments.push((crossed[0], crossed[1])),
+                4 => {
+                    // saddle, the center decides which corners connect
+                    let center: f32 = corners.iter().sum::<f32>() / 4.;
+                    if (center >= 0.) == (corners[0] >= 0.) {
+                        segments.push((crossed[0], crossed[1]));
+                        segments.push((crossed[2], crossed[3]));
+                    } else {
+                        segments.push((crossed[0], crossed[3]));
+                        segments.push((crossed[1], crossed[2]));
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // every grid edge is shared by at most two segments
+    let mut links: HashMap<GridEdge, Vec<usize>> = HashMap::new();
+    for (n, (a, b)) in segments.iter().enumerate() {
+        links.entry(*a).or_default().push(n);
+        links.entry(*b).or_default().push(n);
+    }
+    let mut used = vec![false; segments.len()];
+    let mut lines = vec![];
+    // open lines start at an edge with a single segment, loops anywhere
+    let starts: Vec<usize> = (0..segments.len())
+        .filter(|&n| links[&segments[n].0].len() == 1 || links[&segments[n].1].len() == 1)
+        .chain(0..segments.len())
+        .collect();
+    for start in starts {
+        if used[start] {
+            continue;
+        }
+        let (a, b) = segments[start];
+        let (mut from, mut to) = if links[&b].len() == 1 { (b, a) } else { (a, b) };
+        let mut n = start;
+        let mut points = vec![crossing(from)];
+        loop {
+            used[n] = true;
+            points.push(crossing(to));
+            let next = links[&to].iter().find(|&&m| !used[m]);
+            let Some(&next) = next else {
+                break;
+            };
+            from = to;
+            to = if segments[next].0 == from { segments[next].1 } else { segments[next].0 };
+            n = next;
+        }
+        lines.push(Polyline::new(points));
+    }
+    lines
+}
@@ -1,38 +1,127 @@
+pub mod adhesion;
+pub mod cooling;
 pub mod dlp_slicer;
 pub mod fff_slicer;
+pub mod infill;
+pub mod ordering;
+pub mod placement;
+pub mod seam;
+pub mod support;
+pub mod toolpath;
 
 pub use dlp_slicer::DLPSlicer;
 pub use fff_slicer::FFFSlicer;
 
 use crate::geometry::STLMesh;
-use crate::settings::{FloatOrVecOfFloats, Settings};
+use nalgebra::Vector3;
+use crate::settings::{AdaptiveLayerHeightSettings, Settings};
 
 /// trait for shared behavior between slicers
 pub trait Slicer {
     fn layer_heights(&self, settings: &Settings, stl: &STLMesh) -> Vec<f32> {
+        if let Some(adaptive) = &settings.layer_height.adaptive {
+            return adaptive_layer_heights(stl, settings.layer_height.layer_0_height, adaptive);
+        }
         let bb = stl.bounding_box();
         let mut heights = vec![settings.layer_height.layer_0_height];
+        let mut z = bb.z_min + heights[0];
 
-        let total_height = bb.z_max - bb.z_min - heights[0];
+        // add layers until the top is covered, the last one may stick
+        // out but not so far that its cut misses the mesh, which also
+        // keeps round off in z from adding an empty layer
+        loop {
+            let height = settings.layer_height.layer_n_height.for_layer(heights.len());
+            assert!(height > 0., "Layer heights need to be positive, got {} for layer {}", height, heights.len());
+            if z + 0.5 * height >= bb.z_max {
+                break;
+            }
+            heights.push(height);
+            z += height;
+        }
+        heights
+    }
 
-        let layer_height = match &settings.layer_height.layer_n_height {
-            FloatOrVecOfFloats::Float(x) => x,
-            FloatOrVecOfFloats::VecOfFLoats(_x) => panic!("Got a list for layer n heights")
-        };
+    fn slice(&self, some_file: &str);
+}
 
-        let n_layers = match &settings.layer_height.layer_n_height {
-            FloatOrVecOfFloats::Float(x) => total_height / x,
-            FloatOrVecOfFloats::VecOfFLoats(_x) => panic!("Got a list for layer n heights")
-        };
-        // round up to nearest layer
-        let n_layers = n_layers.ceil() as u32;
+/// layer heights over a first layer of ``layer_0_height`` that follow
+/// the slope of the surface
+///
+/// Every face limits the layers it passes through: a flat face to the
+/// height the quality factor picks between max and min, a tilted one
+/// to that height divided by how much its normal points up or down,
+/// and a vertical one not at all. Each layer gets the smallest limit
+/// of the faces that reach into it at that height.
+pub fn adaptive_layer_heights(
+    stl: &STLMesh,
+    layer_0_height: f32,
+    settings: &AdaptiveLayerHeightSettings
+) -> Vec<f32> {
+    let (min, max) = (settings.min_height, settings.max_height);
+    let flat = max + (min - max) * settings.quality.clamp(0., 1.);
+    // z range and height limit of every face, lowest first
+    let mut faces: Vec<(f32, f32, f32)> = stl.triangles()
+        .iter()
+        .map(|t| {
+            let [a, b, c] = t.map(|x| Vector3::new(x[0], x[1], x[2]));
+            let normal = (b - a).cross(&(c - a));
+            let nz = if normal.norm() > 0. { normal.z.abs() / normal.norm() } else { 0. };
+            let limit = if nz > 0. { (flat / nz).clamp(min, max) } else { max };
+            (a.z.min(b.z).min(c.z), a.z.max(b.z).max(c.z), limit)
+        })
+        .collect();
+    faces.sort_by(|a, b| a.0.total_cmp(&b.0));
 
-        // below doesn't support variable layer height case
-        for _layer in 0..n_layers {
-            heights.push(*layer_height);
+    let bb = stl.bounding_box();
+    let mut heights = vec![layer_0_height];
+    let mut z = bb.z_min + layer_0_height;
+    let mut active: Vec<usize> = vec![];
+    let mut next = 0;
+    while z < bb.z_max {
+        while next < faces.len() && faces[next].0 <= z + max {
+            active.push(next);
+            next += 1;
         }
-        heights
+        active.retain(|&i| faces[i].1 >= z);
+        let height = active
+            .iter()
+            .map(|&i| faces[i])
+            .filter(|(z_min, _, limit)| *z_min <= z + limit)
+            .fold(max, |h, (_, _, limit)| h.min(limit));
+        // cut above the top of the mesh, the layer would be empty
+        if z + 0.5 * height >= bb.z_max {
+            break;
+        }
+        heights.push(height);
+        z += height;
     }
+    heights
+}
 
-    fn slice(&self, some_file: &str);
+/// absolute z of every layer stacked from ``z_min``, the top of the
+/// layer where the nozzle prints it and the plane half way up it
+/// where the mesh is cut
+pub fn layer_zs(heights: &[f32], z_min: f32) -> (Vec<f32>, Vec<f32>) {
+    let mut top = z_min;
+    heights
+        .iter()
+        .map(|height| {
+            top += height;
+            (top, top - 0.5 * height)
+        })
+        .unzip()
+}
+
+/// how many layers from ``heights`` it takes to have at least ``count``
+/// layers and ``thickness``, None when the stack ends first
+pub fn layers_to_reach<'a, I>(mut heights: I, count: u32, thickness: f32) -> Option<usize>
+where
+    I: Iterator<Item = &'a f32>
+{
+    let (mut layers, mut total) = (0, 0.);
+    while layers < count as usize || total < thickness {
+        total += heights.next()?;
+        layers += 1;
+    }
+    Some(layers)
 }
@@ -0,0 +1,179 @@
+use crate::geometry::{ExPolygon, Point};
+use crate::slicer::toolpath::ExtrusionPath;
+
+/// passes of 2-opt over a tour at most, each one is quadratic in its length
+const MAX_PASSES: usize = 8;
+
+/// order in which to print the islands of a layer, starting from ``from``
+///
+/// Islands are visited as the middle of their bounding box.
+pub fn order_islands(islands: &[ExPolygon], from: Option<Point>) -> Vec<usize> {
+    let stops: Vec<(Point, Point)> = islands
+        .iter()
+        .map(|x| {
+            let center = x.bounding_box().center();
+            (center, center)
+        })
+        .collect();
+    tour(&stops, from).into_iter().map(|(i, _)| i).collect()
+}
+
+/// the paths in the order with the least travel found, starting from
+/// ``from``, open paths are turned around where that saves travel
+pub fn order_paths(paths: Vec<ExtrusionPath>, from: Option<Point>) -> Vec<ExtrusionPath> {
+    let stops: Vec<(Point, Point)> = paths.iter().map(|x| (x.start(), x.end())).collect();
+    let order = tour(&stops, from);
+    let mut paths: Vec<Option<ExtrusionPath>> = paths.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|(i, reversed)| {
+            let mut path = paths[i].take().unwrap();
+            // loops keep their direction, they start and end at the seam either way
+            if reversed && path.start() != path.end() {
+                path.polyline.reverse();
+            }
+            path
+        })
+        .collect()
+}
+
+/// rows of solid lines in the order they were laid, run backwards as
+/// a whole when that starts nearer ``from``
+///
+/// Turning the whole group around keeps every row next to the one
+/// laid before it and every line running the same way, so the surface
+/// still comes out even.
+pub fn order_rows(mut paths: Vec<ExtrusionPath>, from: Option<Point>) -> Vec<ExtrusionPath> {
+    let (Some(from), Some(first), Some(last)) = (from, paths.first(), paths.last()) else {
+        return paths;
+    };
+    if (last.end() - from).norm() < (first.start() - from).norm() {
+        paths.reverse();
+        for path in paths.iter_mut() {
+            path.polyline.reverse();
+        }
+    }
+    paths
+}
+
+/// visiting order for stops entered at their first point and left at
+/// their second, as indices and whether the stop is taken backwards
+///
+/// The tour is built nearest neighbour first and then improved with
+/// 2-opt. Reversing a stretch of the tour also turns around every stop
+/// in it, which leaves the travels inside the stretch as long as they
+/// were, so only the two travels at its ends need comparing.
+fn tour(stops: &[(Point, Point)], from: Option<Point>) -> Vec<(usize, bool)> {
+    let n = stops.len();
+    let entry = |(i, reversed): (usize, bool)| if reversed { stops[i].1 } else { stops[i].0 };
+    let exit = |(i, reversed): (usize, bool)| if reversed { stops[i].0 } else { stops[i].1 };
+    // travel to a stop, free where the tour starts or ends
+    let travel = |a: Option<Point>, b: Option<Point>| match (a, b) {
+        (Some(a), Some(b)) => (b - a).norm(),
+        _ => 0.
+    };
+
+    let mut order = Vec::with_capacity(n);
+    let mut visited = vec![false; n];
+    let mut position = from;
+    for _ in 0..n {
+        let next = (0..n)
+            .filter(|&i| !visited[i])
+            .flat_map(|i| [(i, false), (i, true)])
+            .min_by(|&a, &b| travel(position, Some(entry(a))).total_cmp(&travel(position, Some(entry(b)))))
+            .unwrap();
+        visited[next.0] = true;
+        position = Some(exit(next));
+        order.push(next);
+    }
+
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+        for i in 0..n {
+            for j in i..n {
+                let before = if i == 0 { from } else { Some(exit(order[i - 1])) };
+                let after = order.get(j + 1).map(|&x| entry(x));
+                let old = travel(before, Some(entry(order[i]))) + travel(Some(exit(order[j])), after);
+                let new = travel(before, Some(exit(order[j]))) + travel(Some(entry(order[i])), after);
+                if new < old - 1e-3 {
+                    order[i..=j].reverse();
+                    for stop in order[i..=j].iter_mut() {
+                        stop.1 = !stop.1;
+                    }
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Polyline;
+    use crate::slicer::toolpath::{self, PathRole};
+
+    fn path(points: &[(f32, f32)]) -> ExtrusionPath {
+        ExtrusionPath {
+            feed_rate: 1200.,
+            layer_height: 0.2,
+            line_width: 0.4,
+            polyline: Polyline::new(points.iter().map(|&(x, y)| Point::new(x, y)).collect()),
+            role: PathRole::Infill
+        }
+    }
+
+    #[test]
+    fn two_opt_reverses_a_stretch() {
+        // nearest first goes right to 1, back left to -2 and all the
+        // way over to 4.5, starting left at -2 saves two mm
+        let stops: Vec<(Point, Point)> = [1., -2., 4.5]
+            .iter()
+            .map(|&x| (Point::new(x, 0.), Point::new(x, 0.)))
+            .collect();
+        let order: Vec<usize> = tour(&stops, Some(Point::origin()))
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(order, [1, 0, 2]);
+    }
+
+    #[test]
+    fn loops_keep_their_direction() {
+        let square = [(-2., 0.), (-3., 0.), (-3., 1.), (-2., 1.), (-2., 0.)];
+        let paths = vec![path(&[(1., 0.), (1., 0.1)]), path(&square), path(&[(4.5, 0.), (4.5, 0.1)])];
+        let ordered = order_paths(paths, Some(Point::origin()));
+        let looped = ordered
+            .iter()
+            .find(|x| x.start() == x.end())
+            .unwrap();
+        let expected: Vec<Point> = square.iter().map(|&(x, y)| Point::new(x, y)).collect();
+        assert_eq!(looped.polyline.points, expected);
+    }
+
+    #[test]
+    fn rows_travel_no_further() {
+        let rows: Vec<ExtrusionPath> = (0..10)
+            .map(|k| path(&[(0., k as f32), (10., k as f32)]))
+            .collect();
+        let from = Some(Point::new(-1., -1.));
+        let before = toolpath::travel_distance(&rows, from);
+        let ordered = order_paths(rows, from);
+        assert_eq!(ordered.len(), 10);
+        assert!(toolpath::travel_distance(&ordered, from) <= before);
+    }
+
+    #[test]
+    fn rows_turn_around_as_a_whole() {
+        let rows: Vec<ExtrusionPath> = (0..3)
+            .map(|k| path(&[(0., k as f32), (10., k as f32)]))
+            .collect();
+        let ordered = order_rows(rows, Some(Point::new(11., 3.)));
+        let starts: Vec<Point> = ordered.iter().map(|x| x.start()).collect();
+        assert_eq!(starts, [Point::new(10., 2.), Point::new(10., 1.), Point::new(10., 0.)]);
+    }
+}
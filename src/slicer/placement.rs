@@ -0,0 +1,78 @@
+use crate::geometry::{BoundingBox, STLMesh};
+use crate::settings::{BedShape, PrinterSettings, RaftSettings};
+
+/// put the meshes on the bed
+///
+/// Meshes are scaled and rotated about the middle of the group so
+/// their layout relative to each other is kept, the group is then
+/// centered on ``center``, or left where it is in xy when there is
+/// none. Every mesh is dropped so it rests on z = 0.
+pub fn place(stl_meshes: &mut [STLMesh], scale: [f32; 3], rotation: f32, center: Option<[f32; 2]>) {
+    let group = |meshes: &[STLMesh]| meshes
+        .iter()
+        .map(|x| x.bounding_box())
+        .fold(BoundingBox::empty(), |a, b| a.union(&b))
+        .center();
+    let middle = group(stl_meshes);
+    for stl in stl_meshes.iter_mut() {
+        stl.translate(-middle.x, -middle.y, 0.);
+        if scale != [1., 1., 1.] {
+            stl.scale(scale[0], scale[1], scale[2]);
+        }
+        if rotation != 0. {
+            stl.rotate_z(rotation);
+        }
+    }
+
+    // rotating moves the middle of the bounding box
+    let moved = group(stl_meshes);
+    let [x, y] = center.unwrap_or([middle.x, middle.y]);
+    for stl in stl_meshes.iter_mut() {
+        stl.translate(x - moved.x, y - moved.y, 0.);
+        stl.home_z();
+        let bb = stl.bounding_box();
+        println!(
+            "Placed {:?} at x {} to {}, y {} to {}, z {} to {}",
+            stl.file_name(), bb.x_min, bb.x_max, bb.y_min, bb.y_max, bb.z_min, bb.z_max
+        );
+    }
+}
+
+/// panic unless every mesh lies within the build volume of the printer,
+/// a ``raft`` reaches out past the part and lifts it
+pub fn check_build_volume(stl_meshes: &[STLMesh], printer: &PrinterSettings, raft: Option<&RaftSettings>) {
+    // slack for round off from placing
+    let tolerance = 1e-3;
+    let (margin, lift) = raft.map_or((0., 0.), |x| (x.margin.max(0.), x.height()));
+    let [cx, cy] = printer.bed_center();
+    let [width, depth] = printer.bed_size;
+    for stl in stl_meshes {
+        let bb = stl.bounding_box();
+        let fits_bed = match printer.bed_shape {
+            BedShape::Circular => {
+                let radius = 0.5 * width;
+                stl.vertices()
+                    .iter()
+                    .all(|x| (x[0] - cx).hypot(x[1] - cy) + margin <= radius + tolerance)
+            },
+            BedShape::Rectangular => {
+                bb.x_min - margin >= cx - 0.5 * width - tolerance &&
+                bb.x_max + margin <= cx + 0.5 * width + tolerance &&
+                bb.y_min - margin >= cy - 0.5 * depth - tolerance &&
+                bb.y_max + margin <= cy + 0.5 * depth + tolerance
+            }
+        };
+        assert!(
+            fits_bed,
+            "{:?} does not fit on the {:?} bed of {} by {} mm centered at ({}, {}), \
+             it spans x {} to {} and y {} to {} with {} mm of raft around it",
+            stl.file_name(), printer.bed_shape, width, depth, cx, cy,
+            bb.x_min, bb.x_max, bb.y_min, bb.y_max, margin
+        );
+        assert!(
+            bb.z_min >= -tolerance && bb.z_max + lift <= printer.max_height + tolerance,
+            "{:?} spans z {} to {} on {} mm of raft but the printer only reaches 0 to {}",
+            stl.file_name(), bb.z_min, bb.z_max, lift, printer.max_height
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use crate::geometry::{Point, Polygon, Polyline};
+use crate::settings::SeamPosition;
+use std::mem;
+
+/// corners turning less than this many degrees are not corners
+const MIN_CORNER_ANGLE: f32 = 20.;
+
+/// seams closer than this to a point of the loop start at that point
+/// instead of adding one
+const SNAP_DISTANCE: f32 = 0.01;
+
+/// picks where every wall loop starts, layer after layer
+///
+/// Aligned seams follow the seams of the layer below, so the placer
+/// keeps those around from one layer to the next.
+pub struct SeamPlacer {
+    /// seams of the layer being placed
+    current: Vec<Point>,
+    point: Option<Point>,
+    position: SeamPosition,
+    /// seams of the last layer with walls
+    previous: Vec<Point>,
+    /// state of the random number generator
+    random: u64
+}
+
+impl SeamPlacer {
+    pub fn new(position: SeamPosition, point: Option<[f32; 2]>) -> Self {
+        Self {
+            current: vec![],
+            point: point.map(|[x, y]| Point::new(x, y)),
+            position,
+            previous: vec![],
+            random: 0x9e3779b97f4a7c15
+        }
+    }
+
+    /// done with the seams of a layer, the next ones line up with them
+    pub fn next_layer(&mut self) {
+        if !self.current.is_empty() {
+            self.previous = mem::take(&mut self.current);
+        }
+    }
+
+    /// open ``polygon`` at its seam, closing it back on itself, the
+    /// polygon must have points
+    ///
+    /// ``from`` is where the nozzle comes from, nearest seams start as
+    /// close to it as the loop gets. The first layer has nothing to
+    /// align to and aligned seams go in the sharpest corner there.
+    pub fn open(&mut self, polygon: &Polygon, from: Option<Point>) -> Polyline {
+        let target = match (self.point, self.position) {
+            (Some(point), _) => Some(point),
+            (None, SeamPosition::Aligned) => self.previous
+                .iter()
+                .min_by(|a, b| {
+                    let a = polygon.closest_point(a).map_or(f32::INFINITY, |(_, x)| (x - *a).norm_squared());
+                    let b = polygon.closest_point(b).map_or(f32::INFINITY, |(_, x)| (x - *b).norm_squared());
+                    a.total_cmp(&b)
+                })
+                .copied(),
+            (None, SeamPosition::Nearest) => from,
+            _ => None
+        };
+        let seam = match (target.and_then(|x| polygon.closest_point(&x)), self.position) {
+            (Some((i, point)), _) => open_at(polygon, i, point),
+            (None, SeamPosition::Random) => {
+                let i = (self.next_random() % polygon.len() as u64) as usize;
+                polygon.split_at(i)
+            },
+            (None, SeamPosition::Rear) => {
+                let i = (0..polygon.len())
+                    .max_by(|&a, &b| polygon.points[a].y.total_cmp(&polygon.points[b].y))
+                    .unwrap();
+                polygon.split_at(i)
+            },
+            _ => polygon.split_at(sharpest_corner(polygon))
+        };
+        self.current.push(seam.points[0]);
+        seam
+    }
+
+    /// xorshift, the same from run to run so output is repeatable
+    fn next_random(&mut self) -> u64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        self.random
+    }
+}
+
+/// open the loop at ``point`` on the edge starting at point ``i``
+fn open_at(polygon: &Polygon, i: usize, point: Point) -> Polyline {
+    let j = (i + 1) % polygon.len();
+    if (polygon.points[i] - point).norm() < SNAP_DISTANCE {
+        return polygon.split_at(i);
+    }
+    if (polygon.points[j] - point).norm() < SNAP_DISTANCE {
+        return polygon.split_at(j);
+    }
+    let mut points = polygon.points.clone();
+    points.insert(i + 1, point);
+    Polygon::new(points).split_at(i + 1)
+}
+
+/// index of the sharpest concave corner, or the sharpest corner when
+/// there is no concave one
+///
+/// Walls run with the part on their left, so a right turn is a concave
+/// corner of the part.
+fn sharpest_corner(polygon: &Polygon) -> usize {
+    let points = &polygon.points;
+    let n = points.len();
+    let turns: Vec<f32> = (0..n)
+        .map(|i| {
+            let p = points[i];
+            let u = p - points[(i + n - 1) % n];
+            let v = points[(i + 1) % n] - p;
+            (u.x * v.y - u.y * v.x).atan2(u.dot(&v)).to_degrees()
+        })
+        .collect();
+    let sharpest = |concave_only: bool| (0..n)
+        .filter(|&i| !concave_only || turns[i] < -MIN_CORNER_ANGLE)
+        .max_by(|&a, &b| turns[a].abs().total_cmp(&turns[b].abs()));
+    sharpest(true).or(sharpest(false)).unwrap()
+}
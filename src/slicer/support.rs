@@ -0,0 +1,347 @@
+use crate::geometry::polygon::point_segment_distance;
+use crate::geometry::{clipping, offset, ClipType, ExPolygon, JoinType, Point, Polygon, STLMesh};
+use crate::settings::SupportSettings;
+use crate::slicer::layers_to_reach;
+use nalgebra::{Point3, Vector2, Vector3};
+
+/// segments in the circle around a tree branch
+const BRANCH_SEGMENTS: usize = 24;
+
+/// where a tree branch passes through a layer
+#[derive(Clone, Copy, Debug)]
+struct TreeNode {
+    position: Point,
+    radius: f32
+}
+
+/// area of every layer hanging out past the layer below further than
+/// the overhang angle allows
+///
+/// Only layers that ``flagged`` marks, because overhanging faces of the
+/// mesh pass through them, are compared, so faceting noise on steep
+/// walls does not turn into support. Slivers thinner than a line are
+/// dropped.
+pub fn overhangs(
+    regions: &[Vec<ExPolygon>],
+    heights: &[f32],
+    flagged: &[bool],
+    settings: &SupportSettings,
+    line_width: f32
+) -> Vec<Vec<ExPolygon>> {
+    (0..regions.len())
+        .map(|n| {
+            if n == 0 || !flagged[n] {
+                return vec![];
+            }
+            let reach = heights[n] * settings.angle.to_radians().tan();
+            let below = offset::offset_expolygons(&regions[n - 1], reach, JoinType::Round);
+            let overhang = clipping::boolean_ex(&regions[n], &below, ClipType::Difference);
+            offset::open(&overhang, line_width, JoinType::Round)
+        })
+        .collect()
+}
+
+/// layer each overhang's support starts on, the z distance below it
+fn support_tops(overhangs: &[Vec<ExPolygon>], heights: &[f32], z_distance: f32) -> Vec<Option<usize>> {
+    overhangs
+        .iter()
+        .enumerate()
+        .map(|(m, overhang)| {
+            if overhang.is_empty() {
+                return None;
+            }
+            let gap = layers_to_reach(heights[..m].iter().rev(), 0, z_distance)?;
+            (gap < m).then(|| m - 1 - gap)
+        })
+        .collect()
+}
+
+/// support area of every layer
+///
+/// Going from the top down, every overhang starts a column the z
+/// distance below it. Columns are carried down layer by layer and cut
+/// back to the xy distance around the part, so a column that runs
+/// into the part ends there, the z distance above it. With buildplate
+/// only, anything with the part somewhere under it is dropped instead.
+pub fn support_regions(
+    regions: &[Vec<ExPolygon>],
+    overhangs: &[Vec<ExPolygon>],
+    heights: &[f32],
+    settings: &SupportSettings
+) -> Vec<Vec<ExPolygon>> {
+    let count = regions.len();
+    let keep_out: Vec<Vec<ExPolygon>> = regions
+        .iter()
+        .map(|x| offset::offset_expolygons(x, settings.xy_distance, JoinType::Round))
+        .collect();
+
+    // overhangs sorted by the layer their column starts on
+    let mut starts = vec![vec![]; count];
+    for (overhang, top) in overhangs.iter().zip(support_tops(overhangs, heights, settings.z_distance)) {
+        if let Some(top) = top {
+            starts[top].extend(overhang.iter().cloned());
+        }
+    }
+
+    // everything at or below each layer, for buildplate only
+    let mut shadow: Vec<Vec<ExPolygon>> = vec![];
+    if settings.buildplate_only {
+        let mut below: Vec<ExPolygon> = vec![];
+        for x in &keep_out {
+            below = clipping::boolean_ex(&below, x, ClipType::Union);
+            shadow.push(below.clone());
+        }
+    }
+
+    let mut support = vec![vec![]; count];
+    let mut column: Vec<ExPolygon> = vec![];
+    for n in (0..count).rev() {
+        if !starts[n].is_empty() {
+            column = clipping::boolean_ex(&column, &starts[n], ClipType::Union);
+        }
+        if column.is_empty() {
+            continue;
+        }
+        if settings.buildplate_only {
+            column = clipping::boolean_ex(&column, &shadow[n], ClipType::Difference);
+        } else {
+            // stay clear of the part here and the z distance down
+            let gap = layers_to_reach(heights[..n].iter().rev(), 0, settings.z_distance)
+                .unwrap_or(n);
+            for part in &keep_out[n - gap..=n] {
+                column = clipping::boolean_ex(&column, part, ClipType::Difference);
+            }
+        }
+        support[n] = column.clone();
+    }
+    support
+}
+
+/// part of the support of every layer within ``layer_count`` layers of
+/// its top, printed solid so the part has something even to sit on
+pub fn interface_regions(support: &[Vec<ExPolygon>], layer_count: u32) -> Vec<Vec<ExPolygon>> {
+    let count = support.len();
+    (0..count)
+        .map(|n| {
+            let top = n + layer_count as usize;
+            if top >= count {
+                return support[n].clone();
+            }
+            let covered = support[n + 1..=top]
+                .iter()
+                .cloned()
+                .reduce(|a, b| clipping::boolean_ex(&a, &b, ClipType::Intersection));
+            match covered {
+                None => vec![],
+                Some(x) => clipping::boolean_ex(&support[n], &x, ClipType::Difference)
+            }
+        })
+        .collect()
+}
+
+/// tree support area of every layer
+///
+/// Contact points are spread over every overhang on a square grid and
+/// start the z distance below it, far enough from the part to fit. Going down, every node leans toward
+/// its nearest neighbour as far as the branch angle allows, and two
+/// nodes that meet merge into a thicker one. A node that runs into the
+/// part is pushed away from the closest point on the mesh surface, and
+/// if that does not get it clear the branch rests on the part there.
+/// With buildplate only, contact points with the part straight below
+/// them are left out.
+pub fn tree_regions(
+    stl_meshes: &[STLMesh],
+    regions: &[Vec<ExPolygon>],
+    overhangs: &[Vec<ExPolygon>],
+    heights: &[f32],
+    slice_zs: &[f32],
+    settings: &SupportSettings
+) -> Vec<Vec<ExPolygon>> {
+    let count = regions.len();
+    let keep_out: Vec<Vec<ExPolygon>> = regions
+        .iter()
+        .map(|x| offset::offset_expolygons(x, settings.xy_distance, JoinType::Round))
+        .collect();
+
+    let mut starts = vec![vec![]; count];
+    for (overhang, top) in overhangs.iter().zip(support_tops(overhangs, heights, settings.z_distance)) {
+        let Some(top) = top else {
+            continue;
+        };
+        // contacts start clear of the part so the branch can grow straight down
+        let clear = offset::offset_expolygons(&keep_out[top], settings.branch_radius, JoinType::Round);
+        let contact_area = clipping::boolean_ex(overhang, &clear, ClipType::Difference);
+        for position in contact_points(&contact_area, settings.branch_distance) {
+            if settings.buildplate_only {
+                let from = Point3::new(position.x, position.y, slice_zs[top]);
+                let down = Vector3::new(0., 0., -1.);
+                if stl_meshes.iter().any(|x| x.ray_cast(&from, &down).is_some()) {
+                    continue;
+                }
+            }
+            starts[top].push(TreeNode { position, radius: settings.branch_radius });
+        }
+    }
+
+    let mut support = vec![vec![]; count];
+    let mut nodes: Vec<TreeNode> = vec![];
+    for n in (0..count).rev() {
+        nodes.extend(starts[n].iter().copied());
+        if nodes.is_empty() {
+            continue;
+        }
+        let reach = heights[n] * settings.branch_angle.to_radians().tan();
+        nodes = merge(lean(&nodes, reach));
+        nodes.retain_mut(|node| avoid(node, stl_meshes, &regions[n], &keep_out[n], slice_zs[n], reach));
+
+        let discs: Vec<ExPolygon> = nodes
+            .iter()
+            .map(|x| ExPolygon::new(Polygon::circle(x.position, x.radius, BRANCH_SEGMENTS), vec![]))
+            .collect();
+        let discs = clipping::boolean_ex(&discs, &[], ClipType::Union);
+        support[n] = clipping::boolean_ex(&discs, &keep_out[n], ClipType::Difference);
+    }
+    support
+}
+
+/// grid points inside the overhang, ``spacing`` apart, with at least
+/// one point for every island
+fn contact_points(overhang: &[ExPolygon], spacing: f32) -> Vec<Point> {
+    let mut points = vec![];
+    for island in overhang {
+        let bounding_box = island.bounding_box();
+        let mut found: Vec<Point> = vec![];
+        let mut x = (bounding_box.x_min / spacing).ceil() * spacing;
+        while x <= bounding_box.x_max {
+            let mut y = (bounding_box.y_min / spacing).ceil() * spacing;
+            while y <= bounding_box.y_max {
+                let p = Point::new(x, y);
+                if island.contains(&p) {
+                    found.push(p);
+                }
+                y += spacing;
+            }
+            x += spacing;
+        }
+        if found.is_empty() {
+            let center = bounding_box.center();
+            found.push(if island.contains(&center) { center } else { island.contour.points[0] });
+        }
+        points.extend(found);
+    }
+    points
+}
+
+/// every node moved up to ``reach`` toward its nearest neighbour,
+/// stopping half way so the two meet
+fn lean(nodes: &[TreeNode], reach: f32) -> Vec<TreeNode> {
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let nearest = nodes
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, x)| x.position - node.position)
+                .min_by(|a, b| a.norm().total_cmp(&b.norm()));
+            let Some(d) = nearest else {
+                return *node;
+            };
+            let distance = d.norm();
+            if distance == 0. {
+                return *node;
+            }
+            let step = reach.min(distance / 2.);
+            TreeNode { position: node.position + d * (step / distance), radius: node.radius }
+        })
+        .collect()
+}
+
+/// nodes closer than the thicker one's radius joined into one, as
+/// thick as both together and sitting at their weighted center
+fn merge(nodes: Vec<TreeNode>) -> Vec<TreeNode> {
+    let mut merged: Vec<TreeNode> = vec![];
+    for node in nodes {
+        let close = merged
+            .iter_mut()
+            .find(|x| (x.position - node.position).norm() < x.radius.max(node.radius));
+        match close {
+            Some(x) => {
+                let (a, b) = (x.radius.powi(2), node.radius.powi(2));
+                x.position = Point::from((x.position.coords * a + node.position.coords * b) / (a + b));
+                x.radius = (a + b).sqrt();
+            },
+            None => merged.push(node)
+        }
+    }
+    merged
+}
+
+/// keep a node clear of the part, false when the branch has to stop
+///
+/// The node moves away from the closest point of the mesh surface, or
+/// when that lies straight above or below, away from the closest point
+/// of the keep out area.
+fn avoid(
+    node: &mut TreeNode,
+    stl_meshes: &[STLMesh],
+    regions: &[ExPolygon],
+    keep_out: &[ExPolygon],
+    z: f32,
+    reach: f32
+) -> bool {
+    if is_clear(node, keep_out) {
+        return true;
+    }
+    let at = Point3::new(node.position.x, node.position.y, z);
+    let closest = stl_meshes
+        .iter()
+        .filter_map(|x| x.closest_point(&at))
+        .min_by(|a, b| a.distance.total_cmp(&b.distance));
+    let from_mesh = closest.map(|x| Point::new(x.point.x, x.point.y));
+    let from_area = closest_on_boundary(&node.position, keep_out);
+    for closest in [from_mesh, from_area].into_iter().flatten() {
+        let mut away: Vector2<f32> = node.position - closest;
+        if away.norm() < 1e-3 {
+            continue;
+        }
+        if regions.iter().any(|x| x.contains(&node.position)) {
+            away = -away;
+        }
+        let moved = TreeNode { position: node.position + away.normalize() * reach, radius: node.radius };
+        if is_clear(&moved, keep_out) {
+            *node = moved;
+            return true;
+        }
+    }
+    false
+}
+
+/// closest point to ``p`` on the outline of the areas
+fn closest_on_boundary(p: &Point, areas: &[ExPolygon]) -> Option<Point> {
+    areas
+        .iter()
+        .flat_map(|x| x.polygons())
+        .flat_map(|x| x.edges())
+        .map(|(a, b)| {
+            let d = b - a;
+            let t = if d.norm_squared() == 0. {
+                0.
+            } else {
+                ((p - a).dot(&d) / d.norm_squared()).clamp(0., 1.)
+            };
+            a + d * t
+        })
+        .min_by(|a, b| (a - p).norm().total_cmp(&(b - p).norm()))
+}
+
+/// whether the node's disc stays outside the keep out area
+fn is_clear(node: &TreeNode, keep_out: &[ExPolygon]) -> bool {
+    keep_out.iter().all(|x| {
+        !x.contains(&node.position)
+            && x.polygons()
+                .flat_map(|x| x.edges())
+                .all(|(a, b)| point_segment_distance(&node.position, a, b) >= node.radius)
+    })
+}
@@ -0,0 +1,69 @@
+use crate::geometry::{Point, Polyline};
+
+/// what a path is part of
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathRole {
+    /// skin with nothing under it
+    Bridge,
+    Brim,
+    Infill,
+    Skin,
+    Skirt,
+    Support,
+    Wall
+}
+
+/// one extruded polyline, printed from its first point to its last,
+/// feed rates are in mm/min
+#[derive(Clone, Debug)]
+pub struct ExtrusionPath {
+    pub feed_rate: f32,
+    pub layer_height: f32,
+    pub line_width: f32,
+    pub polyline: Polyline,
+    pub role: PathRole
+}
+
+impl ExtrusionPath {
+    pub fn start(&self) -> Point {
+        self.polyline.points[0]
+    }
+
+    pub fn end(&self) -> Point {
+        *self.polyline.points.last().unwrap()
+    }
+}
+
+/// estimated seconds spent extruding and travelling to print the
+/// paths in order, starting from ``from`` when it is known
+///
+/// Every move is taken to run at its feed rate the whole way, which
+/// leaves out acceleration. Travels are left out without a travel
+/// feed rate.
+pub fn layer_time(paths: &[ExtrusionPath], from: Option<Point>, travel_feed_rate: Option<f32>) -> (f32, f32) {
+    let mut extruding = 0.;
+    let mut travelling = 0.;
+    let mut position = from;
+    for path in paths {
+        if let (Some(from), Some(feed_rate)) = (position, travel_feed_rate) {
+            travelling += (path.start() - from).norm() / feed_rate * 60.;
+        }
+        extruding += path.polyline.length() / path.feed_rate * 60.;
+        position = Some(path.end());
+    }
+    (extruding, travelling)
+}
+
+/// length of the travels between the paths in order, starting from
+/// ``from`` when it is known
+pub fn travel_distance(paths: &[ExtrusionPath], from: Option<Point>) -> f32 {
+    let mut distance = 0.;
+    let mut position = from;
+    for path in paths {
+        if let Some(from) = position {
+            distance += (path.start() - from).norm();
+        }
+        position = Some(path.end());
+    }
+    distance
+}